@@ -0,0 +1,37 @@
+// factors the numbers from 255 down to 2 by trial division
+//
+// prints each number followed by its prime factors as "12: 2 2 3"
+
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++[[->+>>>>+<<<<<]>>>>>[-<<<<<+>>>>>]<<<<+[->>>>>>>>>>>>>>>>>>>+<<<<
+<<<<<<<<<<<+<<<<]>>>>[-<<<<+>>>>]>>>>>>>>>>>>>>>>++++++++++<[->-[>+>>]>[+[-<+>]>
++>>]<<<<<]>[-]>>[->>+<<]>>>++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>[-]>>[-<<<<
+<<<<<<<<<<<<<<<<<<<+>+>>>>>>>>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>
+>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<]<[[-]>>>>+>>>>>>>>>>>>>>>>>>>++++++++++++++++
+++++++++++++++++++++++++++++++++.-----------------------------------------------
+-<<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>>>>>[-<<<<<<<<<<<<<<<<<<<<<<+>+>>>>>>
+>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<
+<<]>>>[-<<<<+>>>>]<<<<[[-]>>>>>>>>>>>>>>>>>>>>>>++++++++++++++++++++++++++++++++
+++++++++++++++++.------------------------------------------------<<<<<<<<<<<<<<<
+<<<<<<<]>>>>>>>>>>>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++++.[-]>>>
+>>[-]>[-]<<<<<<<<<<<<<<<<<<<<<<<++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++.----------------------------------------------------------<<<<[->+>>>
++<<<<]>>>>[-<<<<+>>>>]<<[-]++<[->>+>+<<<]>>>[-<<<+>>>]<-[[-]<<[->>>>>>>>>>+<<<<<
+<<+<<<]>>>[-<<<+>>>]<<[->>>>>>>>>>+<<<<<<<<+<<]>>[-<<+>>]>>>>>>>[->-[>+>>]>[+[-<
++>]>+>>]<<<<<]>[-]<<<<<<+>>>>>>>[-<<<<<<<<<+>+>>>>>>>>]<<<<<<<<[->>>>>>>>+<<<<<<
+<<]<[[-]>>[-]>+<<<]>>[[-]<<++++++++++++++++++++++++++++++++.--------------------
+------------<<[->>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<+<<]>>[-<<+>>]>>>>>>>>>>>>>>>>+
++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>[-]>>[->>+<<]>>>++++++++++<[->-[>+>>]>[
++[-<+>]>+>>]<<<<<]>[-]>>[-<<<<<<<<<<<<<<<<<<<<<<<+>+>>>>>>>>>>>>>>>>>>>>>>]<<<<<
+<<<<<<<<<<<<<<<<<[->>>>>>>>>>>>>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<<]<[[-]>>>>+>>>>>
+>>>>>>>>>>>>>>++++++++++++++++++++++++++++++++++++++++++++++++.-----------------
+-------------------------------<<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>>>>>>[-<
+<<<<<<<<<<<<<<<<<<<<<+>+>>>>>>>>>>>>>>>>>>>>>]<<<<<<<<<<<<<<<<<<<<<[->>>>>>>>>>>
+>>>>>>>>>>+<<<<<<<<<<<<<<<<<<<<<]>>>[-<<<<+>>>>]<<<<[[-]>>>>>>>>>>>>>>>>>>>>>>++
+++++++++++++++++++++++++++++++++++++++++++++++.---------------------------------
+---------------<<<<<<<<<<<<<<<<<<<<<<]>>>>>>>>>>>>>>>>>+++++++++++++++++++++++++
++++++++++++++++++++++++.[-]>>>>>[-]>[-]<<<<<<<<<<<<<<<<<<<<<<<<<<[-]>>>>>>>>>>>>
+>[-<<<<<<<<<<<<<+>>>>>>>>>>>>>]<<<<<<<<]>[[-]<<<<<+>>>>>]>>>>>>[-]>[-]<<<<<<<<<<
+<<<[->>+>+<<<]>>>[-<<<+>>>]<-]>++++++++++.----------<<[-]<[-]<[-]<-]
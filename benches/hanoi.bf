@@ -0,0 +1,56 @@
+// towers of hanoi with 16 disks
+//
+// prints the 65535 moves as "disk from to"
+// with the disks named a to p from the smallest and the pegs named A B C
+//
+// there is no recursion as the moves are counted in binary over the disks
+// and the disk moved is the one whose bit turns on
+// each disk always goes round the pegs the same way
+
+>>>>>>>>>>+>>+>>>+>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++>>>>+>>+>>>>++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>+>+
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++>>>>+>>+>>>>++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++>>>>+>>+>>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>
+>+>>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>>
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>>++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>>+>>>>
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++>>>>+>>+>>>+>+++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++>>>>+>
+>+>>>>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++++++<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<
+<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<<+[[-]>>>[->>>>>>>>>>]<[->>>>>>>>+<+<<<<<<<]>
+>>>>>>[-<<<<<<<+>>>>>>>]>[[-]<<<<<<<+>>>>>.>>>++++++++++++++++++++++++++++++++.-
+-------------------------------<<<<<<<[->>>>>+>>+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>
+>]<<[[-]>>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.----
+-------------------------------------------------------------<<]<<<<[->>>>+>>+<<
+<<<<]>>>>>>[-<<<<<<+>>>>>>]<<[[-]>>+++++++++++++++++++++++++++++++++++++++++++++
++++++++++++++++++++++.----------------------------------------------------------
+--------<<]<<<[->>>+>>+<<<<<]>>>>>[-<<<<<+>>>>>]<<[[-]>>++++++++++++++++++++++++
++++++++++++++++++++++++++++++++++++++++++++.------------------------------------
+-------------------------------<<]>>++++++++++++++++++++++++++++++++.-----------
+---------------------<<<<[->>+>>+<<<<]>>>>[-<<<<+>>>>]+<<[[-]>>[-]<<<<<[->>>>+<<
+<<]<[->+<]<[->+<]>>>>>>[-<<<<<<+>>>>>>]<]>>[[-]<<<<<<<[->>>>>>+<<<<<<]>[-<+>]>[-
+<+>]>>>>[-<<<<+>>>>]>]<<<<<<<[->>>>>+>>+<<<<<<<]>>>>>>>[-<<<<<<<+>>>>>>>]<<[[-]>
+>+++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++.-------------
+----------------------------------------------------<<]<<<<[->>>>+>>+<<<<<<]>>>>
+>>[-<<<<<<+>>>>>>]<<[[-]>>++++++++++++++++++++++++++++++++++++++++++++++++++++++
+++++++++++++.------------------------------------------------------------------<
+<]<<<[->>>+>>+<<<<<]>>>>>[-<<<<<+>>>>>]<<[[-]>>+++++++++++++++++++++++++++++++++
+++++++++++++++++++++++++++++++++++.---------------------------------------------
+----------------------<<]>>++++++++++.----------<]<<<<<<<<[->>>>>>>>+<+<<<<<<<]>
+>>>>>>[-<<<<<<<+>>>>>>>]>[-<<<<<<<<<<+>>>>>>>>>>]<<<<<<<<<<<<<<<<<<[>>>>>>>>[-<<
+<<<<<<<<+>>>>>>>>>>]<<<<<<<<<<<<<<<<<<]>>>>>>>>]
@@ -0,0 +1,9 @@
+// long loop stress
+//
+// five nested loops of 40 iterations each around a body no optimization removes
+// then prints the runs of the innermost body modulo 256 as a digit and a newline
+
+++++++++++++++++++++++++++++++++++++++++[>++++++++++++++++++++++++++++++++++++++
+++[>++++++++++++++++++++++++++++++++++++++++[>++++++++++++++++++++++++++++++++++
+++++++[>++++++++++++++++++++++++++++++++++++++++[>+>[-]+<<-]<-]<-]<-]<-]>>>>>+++
++++++++++++++++++++++++++++++++++++++++++++++.[-]++++++++++.
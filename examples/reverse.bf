@@ -0,0 +1,2 @@
+// prints the input reversed
+>,[>,]<[.<]
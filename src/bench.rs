@@ -0,0 +1,177 @@
+use crate::bytecode::OptLevel;
+use crate::engine::{self, EngineConfig};
+use std::fmt::{self, Write};
+use std::time::{Duration, Instant};
+use std::{error, io};
+
+// a program timed by the bench subcommand, run without input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Benchmark {
+    pub name: &'static str,
+    pub codes: &'static str,
+}
+
+pub const BENCHMARKS: [Benchmark; 4] = [
+    Benchmark {
+        name: "mandelbrot",
+        codes: include_str!("../examples/mandelbrot.bf"),
+    },
+    Benchmark {
+        name: "hanoi",
+        codes: include_str!("../benches/hanoi.bf"),
+    },
+    Benchmark {
+        name: "factor",
+        codes: include_str!("../benches/factor.bf"),
+    },
+    Benchmark {
+        name: "long_loop",
+        codes: include_str!("../benches/long_loop.bf"),
+    },
+];
+
+pub fn by_name(name: &str) -> Option<Benchmark> {
+    BENCHMARKS.iter().copied().find(|b| b.name == name)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measurement {
+    pub benchmark: &'static str,
+    pub engine: &'static str, // one of ENGINES
+    pub level: OptLevel,
+    pub compile: Duration, // parsing and optimizing, the best of the runs
+    pub run: Duration,     // executing, the machine code generation of the JIT included
+    pub output: usize,     // bytes
+    pub checksum: u64,     // FNV-1a of the output, which should not change with the engine
+}
+
+impl Measurement {
+    pub const HEADER: &'static str =
+        "benchmark    engine  level         compile         run    output  checksum";
+}
+
+// a row under HEADER
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<12} {:<7} {:<9} {:>10} {:>11} {:>9}  {:016x}",
+            self.benchmark,
+            self.engine,
+            self.level.name(),
+            format!("{:.3?}", self.compile),
+            format!("{:.3?}", self.run),
+            self.output,
+            self.checksum
+        )
+    }
+}
+
+// time benchmark on engine at level, keeping the fastest compile and run out of `runs`
+pub fn measure(
+    benchmark: &Benchmark,
+    engine: &'static str,
+    level: OptLevel,
+    runs: usize,
+) -> Result<Measurement, Box<dyn error::Error>> {
+    let executor = engine::by_name(engine).ok_or_else(|| format!("unknown engine: {engine}"))?;
+    let mut measurement = Measurement {
+        benchmark: benchmark.name,
+        engine,
+        level,
+        compile: Duration::MAX,
+        run: Duration::MAX,
+        output: 0,
+        checksum: 0,
+    };
+    let config = EngineConfig {
+        level,
+        ..Default::default()
+    };
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        let program = config.compile(benchmark.codes)?;
+        measurement.compile = measurement.compile.min(start.elapsed());
+
+        let mut output = vec![];
+        let start = Instant::now();
+        let result = executor.execute(&program, &config, &mut io::empty(), &mut output);
+        result.status?;
+        measurement.run = measurement.run.min(start.elapsed());
+        measurement.output = output.len();
+        measurement.checksum = fnv1a(&output);
+    }
+    Ok(measurement)
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// the measurements of a bench run, in order
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct BenchResults {
+    pub measurements: Vec<Measurement>,
+}
+
+impl BenchResults {
+    // with the times in seconds
+    pub fn to_json(&self) -> String {
+        let mut json = "{\n  \"results\": [".to_string();
+        for (i, m) in self.measurements.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{\"benchmark\": \"{}\", \"engine\": \"{}\", \"level\": \"{}\", \"compile\": {:.6}, \"run\": {:.6}, \"output\": {}, \"checksum\": \"{:016x}\"}}",
+                if i > 0 { "," } else { "" },
+                m.benchmark,
+                m.engine,
+                m.level.name(),
+                m.compile.as_secs_f64(),
+                m.run.as_secs_f64(),
+                m.output,
+                m.checksum
+            );
+        }
+        json.push_str("\n  ]\n}\n");
+        json
+    }
+}
+
+impl fmt::Display for BenchResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", Measurement::HEADER)?;
+        for m in self.measurements.iter() {
+            writeln!(f, "{m}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmarks_agree() {
+        let benchmark = Benchmark {
+            name: "hello_world",
+            codes: include_str!("../examples/hello_world.bf"),
+        };
+        let measurements = engine::ENGINES
+            .iter()
+            .flat_map(|&engine| {
+                OptLevel::ALL.map(|level| measure(&benchmark, engine, level, 1).unwrap())
+            })
+            .collect();
+        let results = BenchResults { measurements };
+        for m in results.measurements.iter() {
+            assert_eq!(results.measurements[0].checksum, m.checksum);
+        }
+        assert!(results.measurements[0].output > 0);
+        assert!(results
+            .to_json()
+            .contains("\"engine\": \"jit\", \"level\": \"full\""));
+    }
+}
@@ -1,144 +1,105 @@
-use crate::token::Token;
+use crate::token::{Span, Token};
 use std::{error, fmt};
+mod brackets;
+mod ir;
 mod optimize;
+mod peephole;
 
-#[derive(PartialEq, Debug)]
+pub use self::ir::{lower, parse, raise, Block, Node};
+pub use self::optimize::optimize;
+pub use self::peephole::peephole;
+
+#[derive(PartialEq, Debug, Clone)]
 pub enum Inst {
     MOVPTR(isize),
     ADD(isize),
     SETZERO,
+    SET(u8),
     MULINTO(isize, isize), // (coef, offset)
     FINDZERO(isize),
+    SCAN(Vec<(isize, isize)>, isize), // (adds as (offset, value), step)
     PUTC,
+    PRINT(Vec<u8>),
     GETC,
-    JZ(usize),
+    DEBUG,     // dump the tape to stderr
+    JZ(usize), // without JNZ for a loop run at most once
     JNZ(usize),
 }
 
-pub fn compile(tokens: &Vec<Token>) -> Result<Vec<Inst>, CompileError> {
-    let mut insts = vec![];
-    let mut addr: usize = 0;
-    let mut acc_val: isize = 1;
-    let mut stack = vec![];
-    let len_tokens = tokens.len();
-
-    for i in 0..len_tokens {
-        match tokens[i] {
-            Token::LT => {
-                if i == len_tokens - 1 || tokens[i + 1] != Token::LT {
-                    insts.push(Inst::MOVPTR(-acc_val));
-                    addr += 1;
-                    acc_val = 1;
-                } else {
-                    acc_val += 1;
-                }
-            }
-            Token::GT => {
-                if i == len_tokens - 1 || tokens[i + 1] != Token::GT {
-                    insts.push(Inst::MOVPTR(acc_val));
-                    addr += 1;
-                    acc_val = 1;
-                } else {
-                    acc_val += 1;
-                }
-            }
-            Token::PLUS => {
-                if i == len_tokens - 1 || tokens[i + 1] != Token::PLUS {
-                    insts.push(Inst::ADD(acc_val));
-                    addr += 1;
-                    acc_val = 1;
-                } else {
-                    acc_val += 1;
-                }
-            }
-            Token::MINUS => {
-                if i == len_tokens - 1 || tokens[i + 1] != Token::MINUS {
-                    insts.push(Inst::ADD(-acc_val));
-                    addr += 1;
-                    acc_val = 1;
-                } else {
-                    acc_val += 1;
-                }
-            }
-            Token::DOT => {
-                insts.push(Inst::PUTC);
-                addr += 1;
-            }
-            Token::COMMA => {
-                insts.push(Inst::GETC);
-                addr += 1;
-            }
-            Token::LSQB => {
-                stack.push((addr, i));
-                insts.push(Inst::JZ(0)); // temp
-                addr += 1;
-            }
-            Token::RSQB => {
-                if let Some((saved_addr, _)) = stack.pop() {
-                    match insts[..] {
-                        // replace [JZ(_), ADD(-1|1), JNZ(_)] (e.g., "[-]")to SETZERO
-                        // TODO: other case in v
-                        [.., Inst::JZ(_), Inst::ADD(v)] if v == -1 || v == 1 => {
-                            insts[addr - 2] = Inst::SETZERO;
-                            insts.pop();
-                            addr -= 1;
-                        }
-
-                        // replace [JZ(_), ADD(-1), MOVPTR(v), ADD(_). MOVPTR(-v), JNZ(_)]
-                        // | [ADD(-1), MOVPTR(v), ADD(_). MOVPTR(-v), JNZ(_)] (e.g., "[->>>+<<<]") to MULINTO
-                        [.., Inst::JZ(_), Inst::ADD(v0), Inst::MOVPTR(p0), Inst::ADD(v1), Inst::MOVPTR(p1)]
-                        | [.., Inst::JZ(_), Inst::MOVPTR(p0), Inst::ADD(v1), Inst::MOVPTR(p1), Inst::ADD(v0)]
-                            if p0.abs() == p1.abs() && p0 != p1 && v0 == -1 =>
-                        {
-                            insts[addr - 5] = Inst::MULINTO(v1, p0);
-                            for _ in 0..4 {
-                                insts.pop();
-                            }
-                            addr -= 4;
-                        }
-
-                        // replace [JZ(_), MOVPTR(v), JNZ(_)] (e.g., "[>>>>>]") to
-                        [.., Inst::JZ(_), Inst::MOVPTR(v)] => {
-                            insts[addr - 2] = Inst::FINDZERO(v);
-                            insts.pop();
-                            addr -= 1;
-                        }
-
-                        _ => {
-                            insts[saved_addr] = Inst::JZ(addr + 1);
-                            insts.push(Inst::JNZ(saved_addr + 1));
-                            addr += 1;
-                        }
-                    }
-                } else {
-                    return Err(CompileError::RSQBMismatch(i));
-                }
-            }
+pub fn compile(tokens: &[Token]) -> Result<Block, CompileError> {
+    Ok(peephole(parse(tokens)?))
+}
+
+// how far Program::optimize goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    NONE,
+    PEEPHOLE, // loops into MULINTO, FINDZERO, SETZERO and SCAN, runs merged
+    FULL,     // and the known cell values folded (see optimize)
+}
+
+impl OptLevel {
+    pub const ALL: [OptLevel; 3] = [OptLevel::NONE, OptLevel::PEEPHOLE, OptLevel::FULL];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OptLevel::NONE => "none",
+            OptLevel::PEEPHOLE => "peephole",
+            OptLevel::FULL => "full",
         }
     }
 
-    if !stack.is_empty() {
-        // TODO
-        let (_, pos) = stack[0];
-        return Err(CompileError::LSQBMismatch(pos));
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|level| level.name() == name)
     }
+}
+
+impl Default for OptLevel {
+    fn default() -> Self {
+        OptLevel::FULL
+    }
+}
 
-    Ok(insts)
+pub fn optimize_with(block: Block, level: OptLevel) -> Block {
+    match level {
+        OptLevel::NONE => block,
+        OptLevel::PEEPHOLE => peephole(block),
+        OptLevel::FULL => optimize(peephole(block)),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum CompileError {
-    LSQBMismatch(usize),
-    RSQBMismatch(usize),
+    LSQBMismatch(Span),
+    RSQBMismatch(Span),
+    Multiple(Vec<CompileError>), // in source order
+}
+
+impl CompileError {
+    pub fn span(&self) -> Option<Span> {
+        use self::CompileError::*;
+        match self {
+            LSQBMismatch(span) | RSQBMismatch(span) => Some(*span),
+            Multiple(_) => None,
+        }
+    }
 }
 
 impl fmt::Display for CompileError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::CompileError::*;
         match self {
-            // TODO
-            LSQBMismatch(pos) => write!(f, "unclosed '[' at pos {pos}"),
-            RSQBMismatch(pos) => write!(f, "unexpected ']' at pos {pos}"),
+            LSQBMismatch(Span { line, col, .. }) => write!(f, "{line}:{col}: unclosed '['"),
+            RSQBMismatch(Span { line, col, .. }) => write!(f, "{line}:{col}: unexpected ']'"),
+            Multiple(errors) => {
+                for (i, e) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{e}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -149,13 +110,31 @@ impl error::Error for CompileError {}
 mod tests {
     use super::Inst::*;
     use super::*;
-    use crate::token::Token::*;
+    use crate::token::TokenKind::{self, *};
+
+    // tokens of a single line without comments
+    fn from_kinds(kinds: Vec<TokenKind>) -> Vec<Token> {
+        kinds
+            .into_iter()
+            .enumerate()
+            .map(|(i, kind)| Token {
+                kind,
+                span: Span {
+                    start: i,
+                    end: i + 1,
+                    line: 1,
+                    col: i + 1,
+                },
+            })
+            .collect()
+    }
+
     #[test]
     fn compile_movev() {
-        let tokens = vec![
+        let tokens = from_kinds(vec![
             GT, GT, LSQB, MINUS, RSQB, LT, LT, LSQB, MINUS, GT, GT, PLUS, LT, LT, RSQB,
-        ];
-        let insts = compile(&tokens);
+        ]);
+        let insts = compile(&tokens).map(|block| lower(block).0);
         assert_eq!(
             vec![MOVPTR(2), SETZERO, MOVPTR(-2), MULINTO(1, 2)],
             insts.unwrap()
@@ -165,7 +144,7 @@ mod tests {
     #[test]
     fn compile_hello_world_red() {
         // "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++."
-        let tokens = vec![
+        let tokens = from_kinds(vec![
             PLUS, PLUS, PLUS, PLUS, PLUS, PLUS, PLUS, PLUS, LSQB, GT, PLUS, PLUS, PLUS, PLUS, LSQB,
             GT, PLUS, PLUS, GT, PLUS, PLUS, PLUS, GT, PLUS, PLUS, PLUS, GT, PLUS, LT, LT, LT, LT,
             MINUS, RSQB, GT, PLUS, GT, PLUS, GT, MINUS, GT, GT, PLUS, LSQB, LT, RSQB, LT, MINUS,
@@ -173,8 +152,8 @@ mod tests {
             PLUS, DOT, DOT, PLUS, PLUS, PLUS, DOT, GT, GT, DOT, LT, MINUS, DOT, LT, DOT, PLUS,
             PLUS, PLUS, DOT, MINUS, MINUS, MINUS, MINUS, MINUS, MINUS, DOT, MINUS, MINUS, MINUS,
             MINUS, MINUS, MINUS, MINUS, MINUS, DOT, GT, GT, PLUS, DOT, GT, PLUS, PLUS, DOT,
-        ];
-        let insts = compile(&tokens);
+        ]);
+        let insts = compile(&tokens).map(|block| lower(block).0);
         assert_eq!(
             vec![
                 ADD(8),
@@ -241,15 +220,21 @@ mod tests {
 
     #[test]
     fn compile_lsqb_error() {
-        let tokens = vec![LSQB, PLUS, PLUS];
-        let insts = compile(&tokens);
-        assert_eq!(Some(CompileError::LSQBMismatch(0)), insts.err());
+        let tokens = from_kinds(vec![LSQB, PLUS, PLUS]);
+        let insts = compile(&tokens).map(|block| lower(block).0);
+        assert_eq!(
+            Some(CompileError::LSQBMismatch(tokens[0].span)),
+            insts.err()
+        );
     }
 
     #[test]
     fn compile_rsqb_error() {
-        let tokens = vec![LSQB, PLUS, PLUS, RSQB, RSQB];
-        let insts = compile(&tokens);
-        assert_eq!(Some(CompileError::RSQBMismatch(4)), insts.err());
+        let tokens = from_kinds(vec![LSQB, PLUS, PLUS, RSQB, RSQB]);
+        let insts = compile(&tokens).map(|block| lower(block).0);
+        assert_eq!(
+            Some(CompileError::RSQBMismatch(tokens[4].span)),
+            insts.err()
+        );
     }
 }
@@ -0,0 +1,115 @@
+use super::CompileError;
+use crate::token::{Token, TokenKind};
+use std::collections::HashMap;
+
+// every bracket mismatch in tokens. of the fewest mismatches explaining the tokens, prefer
+// ones consistent with indentation, where a "]" leading its line closes the "[" of a line
+// indented alike, since the mistake is likely to be there rather than at the outermost "["
+pub fn diagnose(tokens: &[Token]) -> CompileError {
+    let nearest = mismatches(tokens, false);
+    let indented = mismatches(tokens, true);
+    let mut errors = if indented.len() == nearest.len() {
+        indented
+    } else {
+        nearest
+    };
+    match errors.len() {
+        1 => errors.pop().unwrap(),
+        _ => CompileError::Multiple(errors),
+    }
+}
+
+fn mismatches(tokens: &[Token], by_indent: bool) -> Vec<CompileError> {
+    // column of the first token in each line
+    let mut indents = HashMap::new();
+    for token in tokens {
+        indents.entry(token.span.line).or_insert(token.span.col);
+    }
+    let indent = |token: &Token| indents[&token.span.line];
+    let leads = |token: &Token| indent(token) == token.span.col;
+
+    let mut errors = vec![];
+    let mut stack: Vec<&Token> = vec![];
+    for token in tokens {
+        match token.kind {
+            TokenKind::LSQB => stack.push(token),
+            TokenKind::RSQB if by_indent && leads(token) => {
+                match stack.iter().rposition(|open| indent(open) == indent(token)) {
+                    Some(i) => {
+                        // the brackets opened in between are the ones left unclosed
+                        for open in stack.drain(i + 1..) {
+                            errors.push(CompileError::LSQBMismatch(open.span));
+                        }
+                        stack.pop();
+                    }
+                    None if stack
+                        .last()
+                        .map_or(true, |open| indent(open) < indent(token)) =>
+                    {
+                        errors.push(CompileError::RSQBMismatch(token.span));
+                    }
+                    None => {
+                        stack.pop();
+                    }
+                }
+            }
+            TokenKind::RSQB if stack.pop().is_none() => {
+                errors.push(CompileError::RSQBMismatch(token.span));
+            }
+            _ => (),
+        }
+    }
+    for open in stack {
+        errors.push(CompileError::LSQBMismatch(open.span));
+    }
+
+    errors.sort_by_key(|e| e.span().map(|span| span.start));
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::CompileError::*;
+    use crate::token::tokenize;
+
+    fn diagnose_codes(codes: &str) -> CompileError {
+        diagnose(&tokenize(codes).unwrap())
+    }
+
+    #[test]
+    fn diagnose_multiple() {
+        let tokens = tokenize("]+[-]][").unwrap();
+        assert_eq!(
+            Multiple(vec![
+                RSQBMismatch(tokens[0].span),
+                RSQBMismatch(tokens[5].span),
+                LSQBMismatch(tokens[6].span)
+            ]),
+            diagnose(&tokens)
+        );
+    }
+
+    #[test]
+    fn diagnose_unclosed_by_indent() {
+        // the inner "[" is unclosed rather than the outer one
+        let codes = "+[\n  >[-\n  <-\n]\n";
+        let tokens = tokenize(codes).unwrap();
+        assert_eq!(LSQBMismatch(tokens[3].span), diagnose_codes(codes));
+    }
+
+    #[test]
+    fn diagnose_unexpected_by_indent() {
+        let codes = "[\n  [-]\n  ]\n]\n";
+        let tokens = tokenize(codes).unwrap();
+        assert_eq!(RSQBMismatch(tokens[4].span), diagnose_codes(codes));
+    }
+
+    #[test]
+    fn diagnose_inconsistent_indent() {
+        // indentation would explain this with more mismatches, so it is ignored
+        let codes = "[\n  -\n  ]\n[\n";
+        let tokens = tokenize(codes).unwrap();
+        assert_eq!(LSQBMismatch(tokens[3].span), diagnose_codes(codes));
+    }
+}
@@ -0,0 +1,244 @@
+use super::brackets::diagnose;
+use super::{CompileError, Inst};
+use crate::token::{Span, Token, TokenKind};
+
+// JZ/JNZ never appear in `Op`, loops are represented by `Loop` instead
+#[derive(PartialEq, Debug)]
+pub enum Node {
+    Op(Inst, Span),
+    Loop {
+        body: Block,
+        open: Span,
+        close: Span,
+    },
+    // a loop which always exits after the first iteration
+    If {
+        body: Block,
+        open: Span,
+        close: Span,
+    },
+}
+
+pub type Block = Vec<Node>;
+
+// one node per token, runs are merged later by the peephole pass
+pub fn parse(tokens: &[Token]) -> Result<Block, CompileError> {
+    let mut block = vec![];
+    let mut stack = vec![];
+
+    for token in tokens.iter() {
+        let inst = match token.kind {
+            TokenKind::LT => Inst::MOVPTR(-1),
+            TokenKind::GT => Inst::MOVPTR(1),
+            TokenKind::PLUS => Inst::ADD(1),
+            TokenKind::MINUS => Inst::ADD(-1),
+            TokenKind::DOT => Inst::PUTC,
+            TokenKind::COMMA => Inst::GETC,
+            TokenKind::HASH => Inst::DEBUG,
+            TokenKind::BANG => break,
+            TokenKind::LSQB => {
+                stack.push((std::mem::take(&mut block), token.span));
+                continue;
+            }
+            TokenKind::RSQB => {
+                let (outer, open) = stack.pop().ok_or_else(|| diagnose(tokens))?;
+                let body = std::mem::replace(&mut block, outer);
+                block.push(Node::Loop {
+                    body,
+                    open,
+                    close: token.span,
+                });
+                continue;
+            }
+        };
+        block.push(Node::Op(inst, token.span));
+    }
+
+    if !stack.is_empty() {
+        return Err(diagnose(tokens));
+    }
+
+    Ok(block)
+}
+
+// flatten loops into JZ/JNZ, JZ jumps to the next of its JNZ and JNZ to the next of its JZ,
+// and ifs into JZ alone. spans[i] is the source of insts[i]
+pub fn lower(block: Block) -> (Vec<Inst>, Vec<Span>) {
+    let mut insts = vec![];
+    let mut spans = vec![];
+    lower_into(block, &mut insts, &mut spans);
+    (insts, spans)
+}
+
+fn lower_into(block: Block, insts: &mut Vec<Inst>, spans: &mut Vec<Span>) {
+    for node in block {
+        match node {
+            Node::Op(inst, span) => {
+                insts.push(inst);
+                spans.push(span);
+            }
+            Node::Loop { body, open, close } => {
+                let start = insts.len();
+                insts.push(Inst::JZ(0)); // temp
+                spans.push(open);
+                lower_into(body, insts, spans);
+                insts.push(Inst::JNZ(start + 1));
+                spans.push(close);
+                insts[start] = Inst::JZ(insts.len());
+            }
+            Node::If { body, open, .. } => {
+                let start = insts.len();
+                insts.push(Inst::JZ(0)); // temp
+                spans.push(open);
+                lower_into(body, insts, spans);
+                insts[start] = Inst::JZ(insts.len());
+            }
+        }
+    }
+}
+
+// the inverse of lower, spans may be empty. the close of an If is lost in lowering, so the
+// span of its JZ stands for it
+pub fn raise(insts: &[Inst], spans: &[Span]) -> Block {
+    raise_range(insts, spans, 0, insts.len())
+}
+
+fn raise_range(insts: &[Inst], spans: &[Span], start: usize, end: usize) -> Block {
+    let span = |pc: usize| spans.get(pc).copied().unwrap_or_default();
+    let mut block = vec![];
+    let mut pc = start;
+    while pc < end {
+        match insts[pc] {
+            Inst::JZ(exit) if insts.get(exit - 1) == Some(&Inst::JNZ(pc + 1)) => {
+                block.push(Node::Loop {
+                    body: raise_range(insts, spans, pc + 1, exit - 1),
+                    open: span(pc),
+                    close: span(exit - 1),
+                });
+                pc = exit;
+            }
+            Inst::JZ(exit) => {
+                block.push(Node::If {
+                    body: raise_range(insts, spans, pc + 1, exit),
+                    open: span(pc),
+                    close: span(pc),
+                });
+                pc = exit;
+            }
+            ref inst => {
+                block.push(Node::Op(inst.clone(), span(pc)));
+                pc += 1;
+            }
+        }
+    }
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Node::*;
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use crate::token::tokenize;
+
+    // span of codes[start..end] in a single line
+    fn span(start: usize, end: usize) -> Span {
+        Span {
+            start,
+            end,
+            line: 1,
+            col: start + 1,
+        }
+    }
+
+    #[test]
+    fn parse_nested_loop() {
+        let tokens = tokenize("+[>[-]<.]").unwrap();
+        assert_eq!(
+            vec![
+                Op(ADD(1), span(0, 1)),
+                Loop {
+                    body: vec![
+                        Op(MOVPTR(1), span(2, 3)),
+                        Loop {
+                            body: vec![Op(ADD(-1), span(4, 5))],
+                            open: span(3, 4),
+                            close: span(5, 6),
+                        },
+                        Op(MOVPTR(-1), span(6, 7)),
+                        Op(PUTC, span(7, 8))
+                    ],
+                    open: span(1, 2),
+                    close: span(8, 9),
+                }
+            ],
+            parse(&tokens).unwrap()
+        );
+    }
+
+    #[test]
+    fn lower_nested_loop() {
+        let block = parse(&tokenize("+[>[-]<.]").unwrap()).unwrap();
+        assert_eq!(
+            (
+                vec![
+                    ADD(1),
+                    JZ(9),
+                    MOVPTR(1),
+                    JZ(6),
+                    ADD(-1),
+                    JNZ(4),
+                    MOVPTR(-1),
+                    PUTC,
+                    JNZ(2)
+                ],
+                (0..9).map(|i| span(i, i + 1)).collect()
+            ),
+            lower(block)
+        );
+    }
+
+    #[test]
+    fn raise_lowered() {
+        let block = parse(&tokenize("+[>[-]<.]").unwrap()).unwrap();
+        let (insts, spans) = lower(block);
+        assert_eq!(
+            parse(&tokenize("+[>[-]<.]").unwrap()).unwrap(),
+            raise(&insts, &spans)
+        );
+    }
+
+    #[test]
+    fn lower_if() {
+        // "+[>.<[-]]."
+        let block = vec![
+            Op(ADD(1), span(0, 1)),
+            If {
+                body: vec![
+                    Op(MOVPTR(1), span(2, 3)),
+                    Op(PUTC, span(3, 4)),
+                    Op(MOVPTR(-1), span(4, 5)),
+                    Op(SETZERO, span(5, 8)),
+                ],
+                open: span(1, 2),
+                close: span(8, 9),
+            },
+            Op(PUTC, span(9, 10)),
+        ];
+        assert_eq!(
+            (
+                vec![ADD(1), JZ(6), MOVPTR(1), PUTC, MOVPTR(-1), SETZERO, PUTC],
+                vec![
+                    span(0, 1),
+                    span(1, 2),
+                    span(2, 3),
+                    span(3, 4),
+                    span(4, 5),
+                    span(5, 8),
+                    span(9, 10)
+                ]
+            ),
+            lower(block)
+        );
+    }
+}
@@ -1 +1,301 @@
+use super::{Block, Inst, Node};
+use crate::token::Span;
+use crate::vm::MEMSIZE;
+use std::collections::BTreeMap;
 
+// abstract tape state, keyed by the offset from the pointer at the origin of the analysis
+struct State {
+    offset: isize,
+    cells: BTreeMap<isize, Option<u8>>,
+    // cells not in `cells` are known to be zero (only true before the first loop)
+    zeroed: bool,
+    // absolute address of the origin, known while every MOVPTR so far stays in the tape
+    base: Option<isize>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            offset: 0,
+            cells: BTreeMap::new(),
+            zeroed: true,
+            base: Some(MEMSIZE as isize / 2),
+        }
+    }
+
+    // forget everything except that the current cell is `v`
+    fn reset(&mut self, v: Option<u8>) {
+        self.offset = 0;
+        self.cells.clear();
+        self.cells.insert(0, v);
+        self.zeroed = false;
+        self.base = None;
+    }
+
+    fn get(&self, offset: isize) -> Option<u8> {
+        match self.cells.get(&offset) {
+            Some(&v) => v,
+            None if self.zeroed => Some(0),
+            None => None,
+        }
+    }
+
+    fn set(&mut self, offset: isize, v: Option<u8>) {
+        self.cells.insert(offset, v);
+    }
+}
+
+// removes loops never entered, redundant SETZEROs, folds ADDs into SETs
+// and PUTCs into PRINTs by tracking the cell values known at compile time
+pub fn optimize(block: Block) -> Block {
+    optimize_block(block, &mut State::new())
+}
+
+fn optimize_block(block: Block, state: &mut State) -> Block {
+    let mut out: Block = vec![];
+
+    for node in block {
+        let cur = state.offset;
+        let (inst, span) = match node {
+            Node::Loop { body, open, close } => {
+                if state.get(cur) == Some(0) {
+                    // the loop is never entered
+                    continue;
+                }
+                // the body is also reached from the back edge
+                state.reset(None);
+                let body = optimize_block(body, state);
+                state.reset(Some(0));
+                out.push(Node::Loop { body, open, close });
+                continue;
+            }
+            Node::If { body, open, close } => {
+                match state.get(cur) {
+                    // never entered
+                    Some(0) => (),
+                    // always entered, and the body ends with clearing the cell
+                    Some(_) => {
+                        for node in optimize_block(body, state) {
+                            out.push(node);
+                        }
+                    }
+                    None => {
+                        state.reset(None);
+                        let body = optimize_block(body, state);
+                        state.reset(Some(0));
+                        out.push(Node::If { body, open, close });
+                    }
+                }
+                continue;
+            }
+            Node::Op(inst, span) => (inst, span),
+        };
+        match inst {
+            Inst::MOVPTR(v) => {
+                state.offset += v;
+                if let Some(base) = state.base {
+                    if !(0..MEMSIZE as isize).contains(&(base + state.offset)) {
+                        state.base = None;
+                    }
+                }
+                out.push(Node::Op(Inst::MOVPTR(v), span));
+            }
+            Inst::ADD(v) => match state.get(cur) {
+                Some(known) => {
+                    let val = known.wrapping_add(v as u8);
+                    push_set(&mut out, val, span);
+                    state.set(cur, Some(val));
+                }
+                None => out.push(Node::Op(Inst::ADD(v), span)),
+            },
+            Inst::SETZERO => {
+                if state.get(cur) != Some(0) {
+                    push_set(&mut out, 0, span);
+                    state.set(cur, Some(0));
+                }
+            }
+            Inst::SET(v) => {
+                if state.get(cur) != Some(v) {
+                    push_set(&mut out, v, span);
+                    state.set(cur, Some(v));
+                }
+            }
+            Inst::MULINTO(coef, offset) => {
+                // kept even if the source is zero, as it still checks the bound of the target
+                let to = match (state.get(cur), state.get(cur + offset)) {
+                    (Some(src), Some(dst)) => Some(dst.wrapping_add((coef * src as isize) as u8)),
+                    _ => None,
+                };
+                state.set(cur + offset, to);
+                state.set(cur, Some(0));
+                out.push(Node::Op(Inst::MULINTO(coef, offset), span));
+            }
+            Inst::FINDZERO(_) | Inst::SCAN(..) => {
+                if state.get(cur) != Some(0) {
+                    state.reset(Some(0));
+                    out.push(Node::Op(inst, span));
+                }
+            }
+            Inst::PUTC => match state.get(cur) {
+                Some(v) => push_print(&mut out, &[v], span, state.base.is_some()),
+                None => out.push(Node::Op(Inst::PUTC, span)),
+            },
+            Inst::PRINT(s) => push_print(&mut out, &s, span, state.base.is_some()),
+            Inst::GETC => {
+                state.set(cur, None);
+                out.push(Node::Op(Inst::GETC, span));
+            }
+            Inst::DEBUG => out.push(Node::Op(Inst::DEBUG, span)),
+            Inst::JZ(_) | Inst::JNZ(_) => unreachable!(),
+        }
+    }
+
+    out
+}
+
+// a SET/SETZERO immediately followed by another one is a dead store
+fn push_set(out: &mut Block, v: u8, mut span: Span) {
+    if let Some(&Node::Op(Inst::SETZERO | Inst::SET(_), prev)) = out.last() {
+        out.pop();
+        span = prev.to(span);
+    }
+    let inst = if v == 0 { Inst::SETZERO } else { Inst::SET(v) };
+    out.push(Node::Op(inst, span));
+}
+
+// merge into the last PRINT if only instructions which neither fail nor do IO are in between
+fn push_print(out: &mut Block, s: &[u8], span: Span, movptr_safe: bool) {
+    for node in out.iter_mut().rev() {
+        match node {
+            Node::Op(Inst::PRINT(prev), prev_span) => {
+                prev.extend_from_slice(s);
+                *prev_span = prev_span.to(span);
+                return;
+            }
+            Node::Op(Inst::ADD(_) | Inst::SETZERO | Inst::SET(_), _) => (),
+            Node::Op(Inst::MOVPTR(_), _) if movptr_safe => (),
+            _ => break,
+        }
+    }
+    out.push(Node::Op(Inst::PRINT(s.to_vec()), span));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Inst::*;
+    use super::super::{compile, lower};
+    use super::*;
+    use crate::token::tokenize;
+
+    fn optimize_codes(codes: &str) -> Vec<Inst> {
+        lower(optimize(compile(&tokenize(codes).unwrap()).unwrap())).0
+    }
+
+    #[test]
+    fn optimize_comment_loop() {
+        assert_eq!(
+            vec![SET(3), MULINTO(1, 1)],
+            optimize_codes("[comment.]+++[>+<-]")
+        );
+    }
+
+    #[test]
+    fn optimize_redundant_setzero() {
+        assert_eq!(
+            vec![
+                GETC,
+                SETZERO,
+                MOVPTR(1),
+                GETC,
+                JZ(7),
+                PUTC,
+                SETZERO,
+                MOVPTR(-1),
+                SET(1),
+                PRINT(vec![1])
+            ],
+            optimize_codes(",[-][-]>,[.[-]]<[-]+.")
+        );
+    }
+
+    #[test]
+    fn optimize_loop_after_loop() {
+        assert_eq!(
+            vec![GETC, JZ(5), PUTC, GETC, JNZ(2)],
+            optimize_codes(",[.,][.,]")
+        );
+    }
+
+    #[test]
+    fn optimize_mulinto_known() {
+        assert_eq!(
+            vec![
+                SET(2),
+                MOVPTR(1),
+                SET(3),
+                MOVPTR(-1),
+                MULINTO(2, 1),
+                MOVPTR(1),
+                JZ(9),
+                PUTC,
+                JNZ(7)
+            ],
+            optimize_codes("++>+++<[->++<]>[.]")
+        );
+    }
+
+    #[test]
+    fn optimize_print() {
+        let codes = format!(
+            "{}.>{}.<{}..+++.",
+            "+".repeat(72),
+            "+".repeat(101),
+            "+".repeat(36)
+        );
+        assert_eq!(
+            vec![
+                SET(72),
+                PRINT(b"Hello".to_vec()),
+                MOVPTR(1),
+                SET(101),
+                MOVPTR(-1),
+                SET(111)
+            ],
+            optimize_codes(&codes)
+        );
+    }
+
+    #[test]
+    fn optimize_print_not_across_io() {
+        assert_eq!(
+            vec![
+                GETC,
+                MOVPTR(1),
+                SET(1),
+                PRINT(vec![1]),
+                MOVPTR(-1),
+                PUTC,
+                MOVPTR(1),
+                PRINT(vec![1])
+            ],
+            optimize_codes(",>+.<.>.")
+        );
+    }
+
+    #[test]
+    fn optimize_if() {
+        assert_eq!(
+            vec![
+                SET(2),
+                PRINT(vec![2]),
+                SETZERO,
+                MOVPTR(1),
+                GETC,
+                JZ(8),
+                PUTC,
+                SETZERO
+            ],
+            optimize_codes("++[.[-]]>,[.[-]]")
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use super::ir::{Block, Node};
+use super::Inst;
+use crate::token::Span;
+
+// merge runs of the same token and replace idiomatic loops with dedicated instructions
+pub fn peephole(block: Block) -> Block {
+    let mut out: Block = vec![];
+    for node in block {
+        match (out.last_mut(), node) {
+            (Some(Node::Op(Inst::MOVPTR(acc), acc_span)), Node::Op(Inst::MOVPTR(v), span))
+            | (Some(Node::Op(Inst::ADD(acc), acc_span)), Node::Op(Inst::ADD(v), span))
+                if acc.signum() == v.signum() =>
+            {
+                *acc += v;
+                *acc_span = acc_span.to(span);
+            }
+            (_, Node::Loop { body, open, close } | Node::If { body, open, close }) => {
+                out.push(replace_loop(peephole(body), open, close))
+            }
+            (_, node) => out.push(node),
+        }
+    }
+    out
+}
+
+fn replace_loop(body: Block, open: Span, close: Span) -> Node {
+    let inst = match body[..] {
+        // replace [ADD(-1|1)] (e.g., "[-]") to SETZERO
+        // TODO: other case in v
+        [Node::Op(Inst::ADD(v), _)] if v == -1 || v == 1 => Inst::SETZERO,
+
+        // replace [ADD(-1), MOVPTR(v), ADD(_). MOVPTR(-v)]
+        // | [MOVPTR(v), ADD(_). MOVPTR(-v), ADD(-1)] (e.g., "[->>>+<<<]") to MULINTO
+        [Node::Op(Inst::ADD(-1), _), Node::Op(Inst::MOVPTR(p0), _), Node::Op(Inst::ADD(v1), _), Node::Op(Inst::MOVPTR(p1), _)]
+        | [Node::Op(Inst::MOVPTR(p0), _), Node::Op(Inst::ADD(v1), _), Node::Op(Inst::MOVPTR(p1), _), Node::Op(Inst::ADD(-1), _)]
+            if p0 == -p1 =>
+        {
+            Inst::MULINTO(v1, p0)
+        }
+
+        // replace [MOVPTR(v)] (e.g., "[>>>>>]") to FINDZERO
+        [Node::Op(Inst::MOVPTR(v), _)] => Inst::FINDZERO(v),
+
+        // replace [SETZERO] (e.g., "[[-]]") to SETZERO
+        [Node::Op(Inst::SETZERO, _)] => Inst::SETZERO,
+
+        _ => match scan(&body) {
+            Some(inst) => inst,
+            None if runs_once(&body) => return Node::If { body, open, close },
+            None => return Node::Loop { body, open, close },
+        },
+    };
+    Node::Op(inst, open.to(close))
+}
+
+// replace [(ADD(_)|MOVPTR(_))*] moving the pointer (e.g., "[-<]", "[>>-]") to SCAN
+fn scan(body: &[Node]) -> Option<Inst> {
+    let mut offset = 0;
+    let mut adds: Vec<(isize, isize)> = vec![];
+    let mut passed = vec![];
+    for node in body {
+        match node {
+            Node::Op(Inst::MOVPTR(v), _) => {
+                offset += v;
+                passed.push(offset);
+            }
+            Node::Op(Inst::ADD(v), _) => match adds.iter_mut().find(|(o, _)| *o == offset) {
+                Some((_, acc)) => *acc += v,
+                None => adds.push((offset, *v)),
+            },
+            _ => return None,
+        }
+    }
+    if offset == 0 {
+        return None;
+    }
+
+    // SCAN checks the bounds of the cells it touches, which must cover every cell passed
+    let touched = || adds.iter().map(|&(o, _)| o).chain([0, offset]);
+    let (lo, hi) = (touched().min().unwrap(), touched().max().unwrap());
+    if passed.iter().any(|p| !(lo..=hi).contains(p)) {
+        return None;
+    }
+    Some(Inst::SCAN(adds, offset))
+}
+
+// a loop ending with clearing its own cell (e.g., "[>.<[-]]") runs at most once
+fn runs_once(body: &[Node]) -> bool {
+    match body.split_last() {
+        Some((Node::Op(Inst::SETZERO, _), rest)) => balanced(rest),
+        _ => false,
+    }
+}
+
+// whether the pointer is statically known to be back at the start after the block
+fn balanced(block: &[Node]) -> bool {
+    let mut offset = 0;
+    for node in block {
+        match node {
+            Node::Op(Inst::MOVPTR(v), _) => offset += v,
+            Node::Op(Inst::FINDZERO(_) | Inst::SCAN(..), _) => return false,
+            Node::Op(..) => (),
+            Node::Loop { body, .. } | Node::If { body, .. } => {
+                if !balanced(body) {
+                    return false;
+                }
+            }
+        }
+    }
+    offset == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ir::{lower, parse};
+    use super::super::Inst::*;
+    use super::*;
+    use crate::token::tokenize;
+
+    fn peephole_codes(codes: &str) -> Vec<Inst> {
+        lower(peephole(parse(&tokenize(codes).unwrap()).unwrap())).0
+    }
+
+    #[test]
+    fn peephole_scan() {
+        assert_eq!(vec![SCAN(vec![(0, -1)], -1)], peephole_codes("[-<]"));
+        assert_eq!(vec![SCAN(vec![(2, -1)], 2)], peephole_codes("[>>-]"));
+        assert_eq!(
+            vec![SCAN(vec![(-2, -1), (-1, 2)], 1)],
+            peephole_codes("[<<->++>>]")
+        );
+    }
+
+    #[test]
+    fn peephole_scan_passing_untouched_cell() {
+        // passes +2 which SCAN would not check
+        assert_eq!(
+            vec![JZ(4), MOVPTR(2), MOVPTR(-1), JNZ(1)],
+            peephole_codes("[>><]")
+        );
+    }
+
+    #[test]
+    fn peephole_if() {
+        assert_eq!(
+            vec![
+                JZ(9),
+                MOVPTR(1),
+                PUTC,
+                JZ(7),
+                GETC,
+                ADD(-1),
+                JNZ(4),
+                MOVPTR(-1),
+                SETZERO
+            ],
+            peephole_codes("[>.[,-]<[-]]")
+        );
+        assert_eq!(vec![SETZERO], peephole_codes("[[-]]"));
+    }
+
+    #[test]
+    fn peephole_not_if() {
+        // the pointer after "[>]" is unknown
+        assert_eq!(
+            vec![JZ(4), FINDZERO(1), SETZERO, JNZ(1)],
+            peephole_codes("[[>][-]]")
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use crate::bytecode::{self, Block, Inst, Node};
+use crate::emit;
+use crate::vm::{Program, EOF, MEMSIZE};
+use std::fmt::Write;
+
+// the functions used by the generated code, each included only if called
+const AT: &str = "\
+// the pointer q after the instruction at pc, which must be on the tape
+static long at(long q, int pc) {
+    if (q < 0 || q >= MEMSIZE) {
+        fflush(stdout);
+        fprintf(stderr, \"Error: memory out of range: pointer %ld is outside 0..%d (pc %d)\\n\", q, MEMSIZE, pc);
+        exit(3);
+    }
+    return q;
+}
+";
+
+const GET: &str = "\
+static unsigned char get(void) {
+    int c = getchar();
+    return c == EOF ? EOF_CELL : c;
+}
+";
+
+const DEBUG: &str = "\
+static void debug(long p) {
+    long start = p < 4 ? 0 : p - 4, end = p + 5 < MEMSIZE ? p + 5 : MEMSIZE;
+    fflush(stdout);
+    fprintf(stderr, \"#%ld: tape[%ld..%ld] = [\", p, start, end);
+    for (long i = start; i < end; i++) {
+        fprintf(stderr, i > start ? \", %d\" : \"%d\", mem[i]);
+    }
+    fprintf(stderr, \"]\\n\");
+}
+";
+
+// a self-contained C file running program as the VM does: the tape is a static array, a pointer
+// out of it exits with 3 after reporting it as RuntimeError does, and EOF reads as vm::EOF
+pub fn emit(program: &Program) -> String {
+    let mut c = "// generated by bf-jit\n#include <stdio.h>\n#include <stdlib.h>\n\n".to_string();
+    let _ = writeln!(c, "#define MEMSIZE {MEMSIZE}\n#define EOF_CELL {EOF}");
+    let (mem_ptr, cells) = emit::tape(program);
+    // none for a program folded into PRINTs, which would warn of it as unused
+    let tape = emit::uses(program, |inst| !matches!(inst, Inst::PRINT(_)));
+    if tape {
+        c.push_str("\nstatic unsigned char mem[MEMSIZE]");
+        if !cells.is_empty() {
+            let cells: Vec<_> = cells
+                .iter()
+                .map(|(addr, v)| format!("[{addr}] = {v}"))
+                .collect();
+            let _ = write!(c, " = {{{}}}", cells.join(", "));
+        }
+        c.push_str(";\n");
+    }
+    for (used, f) in [
+        (emit::uses(program, emit::checks), AT),
+        (emit::uses(program, |inst| *inst == Inst::GETC), GET),
+        (emit::uses(program, |inst| *inst == Inst::DEBUG), DEBUG),
+    ] {
+        if used {
+            c.push('\n');
+            c.push_str(f);
+        }
+    }
+    c.push_str("\nint main(void) {\n");
+    if tape {
+        let _ = writeln!(c, "    long p = {mem_ptr};");
+    }
+    block(
+        &mut c,
+        &bytecode::raise(&program.bytecodes, &program.spans),
+        0,
+        1,
+    );
+    c.push_str("    return 0;\n}\n");
+    c
+}
+
+// the statements of block lowered from pc on, returning the pc after it
+fn block(c: &mut String, block: &Block, mut pc: usize, depth: usize) -> usize {
+    let indent = "    ".repeat(depth);
+    for node in block {
+        pc = match node {
+            Node::Op(inst, _) => {
+                let _ = writeln!(c, "{indent}{}", statement(inst, pc, &indent));
+                pc + 1
+            }
+            Node::Loop { body, .. } => {
+                let _ = writeln!(c, "{indent}while (mem[p]) {{");
+                let exit = self::block(c, body, pc + 1, depth + 1) + 1;
+                let _ = writeln!(c, "{indent}}}");
+                exit
+            }
+            Node::If { body, .. } => {
+                let _ = writeln!(c, "{indent}if (mem[p]) {{");
+                let exit = self::block(c, body, pc + 1, depth + 1);
+                let _ = writeln!(c, "{indent}}}");
+                exit
+            }
+        };
+    }
+    pc
+}
+
+// the C of the instruction at pc, other than a jump
+fn statement(inst: &Inst, pc: usize, indent: &str) -> String {
+    match inst {
+        Inst::MOVPTR(v) => format!("p = at(p {}, {pc});", signed(*v)),
+        Inst::ADD(v) => format!("mem[p] {}= {};", if *v < 0 { '-' } else { '+' }, v.abs()),
+        Inst::SETZERO => "mem[p] = 0;".to_string(),
+        Inst::SET(v) => format!("mem[p] = {v};"),
+        Inst::MULINTO(coef, offset) => {
+            let product = match coef.abs() {
+                1 => "mem[p]".to_string(),
+                n => format!("mem[p] * {n}"),
+            };
+            format!(
+                "mem[at(p {}, {pc})] {}= {product};\n{indent}mem[p] = 0;",
+                signed(*offset),
+                if *coef < 0 { '-' } else { '+' },
+            )
+        }
+        Inst::FINDZERO(v) => format!("while (mem[p]) p = at(p {}, {pc});", signed(*v)),
+        Inst::SCAN(adds, step) => {
+            let mut body = "while (mem[p]) {\n".to_string();
+            for (offset, v) in adds.iter() {
+                let _ = writeln!(
+                    body,
+                    "{indent}    mem[at(p {}, {pc})] {}= {};",
+                    signed(*offset),
+                    if *v < 0 { '-' } else { '+' },
+                    v.abs()
+                );
+            }
+            let _ = write!(
+                body,
+                "{indent}    p = at(p {}, {pc});\n{indent}}}",
+                signed(*step)
+            );
+            body
+        }
+        Inst::PUTC => "putchar(mem[p]);".to_string(),
+        // "?" for the trigraphs
+        Inst::PRINT(s) => format!(
+            "fwrite(\"{}\", 1, {}, stdout);",
+            emit::escape(s, b"\"\\?", |b| format!("\\{b:03o}")),
+            s.len()
+        ),
+        Inst::GETC => "mem[p] = get();".to_string(),
+        Inst::DEBUG => "debug(p);".to_string(),
+        Inst::JZ(_) | Inst::JNZ(_) => unreachable!("raised into loops and ifs"),
+    }
+}
+
+// "+ v" or "- v"
+fn signed(v: isize) -> String {
+    format!("{} {}", if v < 0 { '-' } else { '+' }, v.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    #[test]
+    fn emit_c() {
+        // a loop, an if and a PRINT to escape
+        let program = Program {
+            bytecodes: vec![
+                GETC,
+                JZ(4),
+                MULINTO(2, 1),
+                JNZ(2),
+                MOVPTR(1),
+                JZ(7),
+                PRINT(b"\"a\"\n\x01".to_vec()),
+                ADD(-3),
+            ],
+            ..Default::default()
+        };
+        let c = emit(&program);
+        assert!(c.contains(&format!("#define EOF_CELL {EOF}")));
+        assert!(!c.contains("static void debug"));
+        assert!(c.ends_with(
+            "int main(void) {
+    long p = 50000;
+    mem[p] = get();
+    while (mem[p]) {
+        mem[at(p + 1, 2)] += mem[p] * 2;
+        mem[p] = 0;
+    }
+    p = at(p + 1, 4);
+    if (mem[p]) {
+        fwrite(\"\\\"a\\\"\\n\\001\", 1, 5, stdout);
+    }
+    mem[p] -= 3;
+    return 0;
+}
+"
+        ));
+    }
+
+    // builds the C of hello_world and reverse with cc if there is one
+    #[test]
+    fn emit_c_compiles() {
+        for (name, codes, input, expected) in [
+            (
+                "hello_world",
+                include_str!("../examples/hello_world.bf"),
+                "",
+                include_str!("../examples/hello_world.out"),
+            ),
+            (
+                "reverse",
+                include_str!("../examples/reverse.bf"),
+                include_str!("../examples/reverse.in"),
+                include_str!("../examples/reverse.out"),
+            ),
+        ] {
+            let c = crate::compile_c(codes, &Default::default()).unwrap();
+
+            let dir = env::temp_dir();
+            let source = dir.join(format!("bf-jit-{}-{name}.c", process::id()));
+            let binary = dir.join(format!("bf-jit-{}-{name}", process::id()));
+            fs::write(&source, c).unwrap();
+            let status = process::Command::new("cc")
+                .args(["-std=c99", "-Wall", "-Werror", "-O2", "-o"])
+                .args([&binary, &source])
+                .status();
+            fs::remove_file(&source).unwrap();
+            match status {
+                Ok(status) => assert!(status.success()),
+                Err(_) => return,
+            }
+            let mut child = process::Command::new(&binary)
+                .stdin(process::Stdio::piped())
+                .stdout(process::Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(input.as_bytes())
+                .unwrap();
+            let output = child.wait_with_output().unwrap();
+            fs::remove_file(&binary).unwrap();
+            assert_eq!(expected.as_bytes(), output.stdout);
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::bytecode::Inst;
+use crate::diagnostic::snippet;
+use crate::vm::{dump, Program, RuntimeError, Step, TapeWindow, VM};
+use std::io::{self, Write};
+
+pub const HELP: &str = "\
+s, step [N]          run N instructions, each one source character (default 1)
+c, continue          run until a breakpoint, a watchpoint or the end
+f, finish            run until the innermost loop exits
+b, break LINE:COL    stop before the code at LINE:COL
+b, break #           stop before every \"#\"
+w, watch [ADDR]      stop when the cell ADDR (default: the current one) changes
+d, delete N          delete the breakpoint or watchpoint N
+i, info              list the breakpoints and watchpoints
+p, print [ADDR]      print the tape around ADDR (default: the pointer)
+l, list              print the current position
+q, quit              stop debugging
+an empty line repeats the last command";
+
+enum Point {
+    Break {
+        offset: usize,
+        line: usize,
+        col: usize,
+    },
+    Debug,
+    Watch {
+        addr: usize,
+        value: u8,
+    },
+}
+
+enum Resume {
+    Insts(usize),
+    Continue,
+    Finish(usize), // the pc after the loop
+}
+
+// runs a program on VM step by step as told by the commands in HELP
+pub struct Debugger<'a> {
+    program: &'a Program,
+    source: &'a str,
+    vm: VM,
+    points: Vec<Option<Point>>, // None once deleted, to keep the numbers
+    error: Option<RuntimeError>,
+    started: bool, // the points before the first instruction were checked
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a Program, source: &'a str) -> Self {
+        Self {
+            program,
+            source,
+            vm: VM::for_program(program),
+            points: vec![],
+            error: None,
+            started: false,
+        }
+    }
+
+    // read commands until `quit` or the end of them, and return the error which stopped the program
+    pub fn run<R: io::Read, W: io::Write>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+        commands: &mut dyn FnMut() -> Option<String>,
+        log: &mut dyn Write,
+    ) -> Result<(), RuntimeError> {
+        self.show(log);
+        let mut last = String::new();
+        loop {
+            let _ = write!(log, "(bf) ");
+            let _ = log.flush();
+            let line = match commands() {
+                Some(line) => line,
+                None => break,
+            };
+            if !line.trim().is_empty() {
+                last = line.trim().to_string();
+            }
+            let mut words = last.split_whitespace();
+            let (cmd, arg) = (words.next().unwrap_or(""), words.next());
+            let count = || {
+                arg.map_or(Ok(1), |n| {
+                    n.parse().ok().filter(|&n| n > 0).ok_or("expected a count")
+                })
+            };
+
+            let res = match cmd {
+                "s" | "step" => {
+                    count().and_then(|n| self.resume(Resume::Insts(n), reader, writer, log))
+                }
+                "c" | "continue" => self.resume(Resume::Continue, reader, writer, log),
+                "f" | "finish" => match self.loop_exit() {
+                    Some(exit) => self.resume(Resume::Finish(exit), reader, writer, log),
+                    None => Err("not in a loop"),
+                },
+                "b" | "break" => self.add_break(arg),
+                "w" | "watch" => self.add_watch(arg),
+                "d" | "delete" => arg
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .and_then(|n| self.points.get_mut(n.wrapping_sub(1))?.take())
+                    .map(|_| ())
+                    .ok_or("no such breakpoint or watchpoint"),
+                "i" | "info" => {
+                    self.info(log);
+                    Ok(())
+                }
+                "p" | "print" => match arg.map(str::parse::<isize>) {
+                    None => {
+                        let _ = writeln!(log, "{}", dump(self.vm.mem(), self.vm.mem_ptr()));
+                        Ok(())
+                    }
+                    Some(Ok(addr)) => {
+                        let _ = writeln!(log, "{}", TapeWindow::around(self.vm.mem(), addr));
+                        Ok(())
+                    }
+                    Some(Err(_)) => Err("expected an address"),
+                },
+                "l" | "list" => {
+                    self.show(log);
+                    Ok(())
+                }
+                "q" | "quit" => break,
+                "h" | "help" => {
+                    let _ = writeln!(log, "{HELP}");
+                    Ok(())
+                }
+                _ => Err("unknown command, try \"help\""),
+            };
+            if let Err(msg) = res {
+                let _ = writeln!(log, "{msg}");
+            }
+        }
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.error.is_some() || self.vm.pc() >= self.program.bytecodes.len()
+    }
+
+    fn resume<R: io::Read, W: io::Write>(
+        &mut self,
+        mode: Resume,
+        reader: &mut R,
+        writer: &mut W,
+        log: &mut dyn Write,
+    ) -> Result<(), &'static str> {
+        if self.finished() {
+            return Err("the program is not running");
+        }
+        let mut remaining = match mode {
+            Resume::Insts(n) => n,
+            _ => 0,
+        };
+        // no step comes before the first instruction to check the points after
+        if !self.started {
+            self.started = true;
+            if self.hit(log) {
+                self.show(log);
+                return Ok(());
+            }
+        }
+        loop {
+            let res = self.vm.step(self.program, reader, writer);
+            let _ = writer.flush();
+            match res {
+                Ok(Step::Next) => (),
+                Ok(Step::NeedInput) => return Err("the program is waiting for input"),
+                Err(e) => {
+                    let _ = writeln!(log, "the program stopped: {e}");
+                    self.error = Some(e);
+                    self.show(log);
+                    return Ok(());
+                }
+            }
+            if self.finished() {
+                let _ = writeln!(log, "the program exited");
+                return Ok(());
+            }
+            if self.hit(log) {
+                self.show(log);
+                return Ok(());
+            }
+            let done = match mode {
+                Resume::Insts(_) => {
+                    remaining -= 1;
+                    remaining == 0
+                }
+                Resume::Continue => false,
+                Resume::Finish(exit) => self.vm.pc() == exit,
+            };
+            if done {
+                self.show(log);
+                return Ok(());
+            }
+        }
+    }
+
+    // report the points hit before the instruction at pc, updating the watched values
+    fn hit(&mut self, log: &mut dyn Write) -> bool {
+        let pc = self.vm.pc();
+        let (mem, span) = (self.vm.mem(), self.program.spans.get(pc));
+        let mut hit = false;
+        for (i, point) in self.points.iter_mut().enumerate() {
+            match point {
+                Some(Point::Break { offset, .. })
+                    if span.map_or(false, |span| (span.start..span.end).contains(offset)) =>
+                {
+                    let _ = writeln!(log, "breakpoint {}", i + 1);
+                    hit = true;
+                }
+                Some(Point::Debug) if self.program.bytecodes[pc] == Inst::DEBUG => {
+                    let _ = writeln!(log, "breakpoint {} (#)", i + 1);
+                    hit = true;
+                }
+                Some(Point::Watch { addr, value }) if mem[*addr] != *value => {
+                    let _ = writeln!(
+                        log,
+                        "watchpoint {}: cell {addr} changed from {value} to {}",
+                        i + 1,
+                        mem[*addr]
+                    );
+                    *value = mem[*addr];
+                    hit = true;
+                }
+                _ => (),
+            }
+        }
+        hit
+    }
+
+    // the pc right after the innermost loop around pc
+    fn loop_exit(&self) -> Option<usize> {
+        let pc = self.vm.pc();
+        self.program
+            .bytecodes
+            .iter()
+            .take(pc + 1)
+            .enumerate()
+            .rev()
+            .find_map(|(addr, inst)| match *inst {
+                Inst::JZ(exit) if addr <= pc && pc < exit => Some(exit),
+                _ => None,
+            })
+    }
+
+    fn add_break(&mut self, arg: Option<&str>) -> Result<(), &'static str> {
+        let point = match arg {
+            Some("#") => Point::Debug,
+            Some(pos) => {
+                let (line, col) = pos
+                    .split_once(':')
+                    .and_then(|(l, c)| Some((l.parse().ok()?, c.parse().ok()?)))
+                    .ok_or("expected LINE:COL or #")?;
+                let offset = offset_of(self.source, line, col).ok_or("no such position")?;
+                Point::Break { offset, line, col }
+            }
+            None => return Err("expected LINE:COL or #"),
+        };
+        self.points.push(Some(point));
+        Ok(())
+    }
+
+    fn add_watch(&mut self, arg: Option<&str>) -> Result<(), &'static str> {
+        let addr = match arg {
+            Some(addr) => addr.parse().map_err(|_| "expected an address")?,
+            None => self.vm.mem_ptr(),
+        };
+        let value = *self.vm.mem().get(addr).ok_or("out of the tape")?;
+        self.points.push(Some(Point::Watch { addr, value }));
+        Ok(())
+    }
+
+    fn info(&self, log: &mut dyn Write) {
+        for (i, point) in self.points.iter().enumerate() {
+            let _ = match point {
+                Some(Point::Break { line, col, .. }) => {
+                    writeln!(log, "{}: break at {line}:{col}", i + 1)
+                }
+                Some(Point::Debug) => writeln!(log, "{}: break at #", i + 1),
+                Some(Point::Watch { addr, value }) => {
+                    writeln!(log, "{}: watch cell {addr} (now {value})", i + 1)
+                }
+                None => continue,
+            };
+        }
+    }
+
+    // the next instruction and its source
+    fn show(&self, log: &mut dyn Write) {
+        let pc = self.vm.pc();
+        let inst = match self.program.bytecodes.get(pc) {
+            Some(inst) => inst,
+            None => {
+                let _ = writeln!(log, "pc {pc}: the end of the program");
+                return;
+            }
+        };
+        let _ = writeln!(log, "pc {pc}: {inst:?}, ptr {}", self.vm.mem_ptr());
+        if let Some(&span) = self.program.spans.get(pc) {
+            let _ = write!(log, "{}", snippet(self.source, span));
+        }
+    }
+}
+
+// the byte offset of LINE:COL (1-origin, in chars)
+fn offset_of(source: &str, line: usize, col: usize) -> Option<usize> {
+    let mut start = 0;
+    for (i, text) in source.split_inclusive('\n').enumerate() {
+        if i + 1 == line {
+            return text
+                .char_indices()
+                .nth(col.checked_sub(1)?)
+                .map(|(offset, _)| start + offset);
+        }
+        start += text.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{lower, parse};
+    use crate::token::{tokenize_with, Extensions};
+
+    // run codes under the commands, and return the log and the output
+    fn debug(codes: &str, script: &str) -> (String, Vec<u8>) {
+        let extensions = Extensions {
+            debug: true,
+            ..Default::default()
+        };
+        let (bytecodes, spans) = lower(parse(&tokenize_with(codes, extensions).unwrap()).unwrap());
+        let program = Program {
+            bytecodes,
+            spans,
+            ..Default::default()
+        };
+        let mut lines = script.lines().map(String::from);
+        let (mut log, mut output) = (vec![], vec![]);
+        let _ = Debugger::new(&program, codes).run(
+            &mut io::empty(),
+            &mut output,
+            &mut || lines.next(),
+            &mut log,
+        );
+        (String::from_utf8(log).unwrap(), output)
+    }
+
+    #[test]
+    fn debug_break_and_continue() {
+        let (log, output) = debug("+.\n+.+.", "b 2:3\nc\np\nc");
+        assert!(log.contains("breakpoint 1\npc 4: ADD(1), ptr 50000\n"));
+        assert!(log.contains("#50000: tape[49996..50005] = [0, 0, 0, 0, 2, 0, 0, 0, 0]"));
+        assert!(log.ends_with("the program exited\n(bf) "));
+        assert_eq!(vec![1, 2, 3], output);
+    }
+
+    #[test]
+    fn debug_break_first() {
+        let (log, output) = debug("+.", "b 1:1\nc\nc");
+        assert!(log.contains("breakpoint 1\npc 0: ADD(1), ptr 50000\n"));
+        assert!(log.ends_with("the program exited\n(bf) "));
+        assert_eq!(vec![1], output);
+    }
+
+    #[test]
+    fn debug_step_and_repeat() {
+        let (log, output) = debug("+++.", "s 2\n\n\n");
+        assert_eq!(vec![3], output);
+        assert!(log.contains("pc 2: ADD(1)"));
+        assert!(log.contains("the program exited\n"));
+        assert!(log.ends_with("the program is not running\n(bf) "));
+    }
+
+    #[test]
+    fn debug_watch_and_finish() {
+        let (log, _) = debug("++[>+<-]>#.", "w 50001\nc\nd 1\nf\nb #\nc\nl");
+        assert!(log.contains("watchpoint 1: cell 50001 changed from 0 to 1"));
+        assert!(log.contains("pc 8: MOVPTR(1)"));
+        assert!(log.contains("breakpoint 2 (#)\npc 9: DEBUG"));
+    }
+}
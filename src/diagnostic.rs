@@ -0,0 +1,66 @@
+use crate::token::Span;
+
+// the source line of span with the span underlined, e.g.
+//   |
+// 1 | [.]]
+//   |    ^
+pub fn snippet(source: &str, span: Span) -> String {
+    let text = source
+        .lines()
+        .nth(span.line.saturating_sub(1))
+        .unwrap_or("");
+    let gutter = " ".repeat(span.line.to_string().len());
+
+    // keep tabs so that the carets line up
+    let indent: String = text
+        .chars()
+        .take(span.col.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source
+        .get(span.start..span.end)
+        .and_then(|s| s.lines().next())
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+
+    format!(
+        "{gutter} |\n{} | {text}\n{gutter} | {indent}{}\n",
+        span.line,
+        "^".repeat(width)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snippet_single_char() {
+        let span = Span {
+            start: 12,
+            end: 13,
+            line: 2,
+            col: 6,
+        };
+        assert_eq!(
+            "  |\n2 | \t+[.]]\n  | \t    ^\n",
+            snippet("++++++\n\t+[.]]\n", span)
+        );
+    }
+
+    #[test]
+    fn snippet_multi_line() {
+        let span = Span {
+            start: 1,
+            end: 9,
+            line: 1,
+            col: 2,
+        };
+        assert_eq!("  |\n1 | +[->\n  |  ^^^\n", snippet("+[->\n+<]\n", span));
+    }
+
+    #[test]
+    fn snippet_default_span() {
+        assert_eq!("  |\n0 | +[->\n  | ^\n", snippet("+[->\n", Span::default()));
+    }
+}
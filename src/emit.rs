@@ -0,0 +1,52 @@
+// the parts shared by the emitters: c, wasm and the ones of jit
+use crate::bytecode::Inst;
+use crate::vm::{Program, MEMSIZE};
+
+// the pointer and the non-zero cells the program starts from
+pub fn tape(program: &Program) -> (usize, &[(usize, u8)]) {
+    match &program.init {
+        Some(init) => (init.mem_ptr, &init.cells[..]),
+        None => (MEMSIZE / 2, &[][..]),
+    }
+}
+
+// whether any instruction of program is one of f
+pub fn uses(program: &Program, f: fn(&Inst) -> bool) -> bool {
+    program.bytecodes.iter().any(f)
+}
+
+// the instructions checking an address against the tape
+pub fn checks(inst: &Inst) -> bool {
+    matches!(
+        inst,
+        Inst::MOVPTR(_) | Inst::MULINTO(..) | Inst::FINDZERO(_) | Inst::SCAN(..)
+    )
+}
+
+// the bytes of a string literal: the printable ones as they are, with a backslash before those
+// in `quoted`, a newline as \n and the others as `byte` writes them
+pub fn escape(s: &[u8], quoted: &[u8], byte: fn(u8) -> String) -> String {
+    s.iter()
+        .map(|&b| match b {
+            _ if quoted.contains(&b) => format!("\\{}", b as char),
+            b'\n' => "\\n".to_string(),
+            0x20..=0x7e => (b as char).to_string(),
+            _ => byte(b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_bytes() {
+        let octal = |b| format!("\\{b:03o}");
+        assert_eq!(
+            "a\\\"\\\\?\\n\\001",
+            escape(b"a\"\\?\n\x01", b"\"\\", octal)
+        );
+        assert_eq!("\\?\\377", escape(b"?\xff", b"?", octal));
+    }
+}
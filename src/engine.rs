@@ -0,0 +1,133 @@
+use crate::bytecode::OptLevel;
+use crate::vm::{Limits, Program, RuntimeError, Snapshot, Status, VM};
+use std::sync::Arc;
+use std::{error, fmt, io};
+
+// how a program is compiled and run, the same for every engine
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineConfig {
+    pub limits: Limits,
+    pub level: OptLevel,
+}
+
+impl EngineConfig {
+    // parse codes and optimize them up to level
+    pub fn compile(&self, codes: &str) -> Result<Program, Box<dyn error::Error>> {
+        Ok(crate::parse(codes)?.optimize(self.level))
+    }
+}
+
+// how a run of Engine::execute ended, the same for every engine
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub status: Result<Status, RuntimeError>, // Finished, or the error which stopped the program
+    pub steps: u64,
+    pub state: Snapshot, // also after an error
+}
+
+// a way to execute a program on a VM, from its current state to the end. the VM carries the
+// limits and is left in the final state
+pub trait Engine: fmt::Debug {
+    fn run(
+        &self,
+        vm: &mut VM,
+        program: &Program,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RuntimeError>;
+
+    // run program, e.g. from EngineConfig::compile, from its start within config.limits
+    fn execute(
+        &self,
+        program: &Program,
+        config: &EngineConfig,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
+    ) -> RunResult {
+        let mut vm = VM::for_program(program);
+        vm.limit(config.limits);
+        let status = self
+            .run(&mut vm, program, reader, writer)
+            .map(|()| Status::Finished);
+        RunResult {
+            status,
+            steps: vm.steps(),
+            state: vm.snapshot(),
+        }
+    }
+}
+
+// executes one instruction at a time with VM::step
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interpreter;
+
+impl Engine for Interpreter {
+    fn run(
+        &self,
+        vm: &mut VM,
+        program: &Program,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RuntimeError> {
+        vm.interpret(program, reader, writer)
+    }
+}
+
+// compiles the program into x86-64 code and runs it natively
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Jit;
+
+impl Engine for Jit {
+    fn run(
+        &self,
+        vm: &mut VM,
+        program: &Program,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RuntimeError> {
+        vm.run_jit(program, reader, writer)
+    }
+}
+
+// the names of by_name, the default first
+pub const ENGINES: [&str; 2] = ["interp", "jit"];
+
+pub fn by_name(name: &str) -> Option<Arc<dyn Engine>> {
+    match name {
+        "interp" | "interpreter" => Some(Arc::new(Interpreter)),
+        "jit" => Some(Arc::new(Jit)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn execute_by_engine() {
+        // running off either edge of the tape, and stopped by a limit before
+        let config = EngineConfig::default();
+        let limited = EngineConfig {
+            limits: Limits {
+                max_steps: Some(1000),
+                ..Default::default()
+            },
+            ..config
+        };
+        for codes in ["+[>+]", "+[<+]"] {
+            let program = config.compile(codes).unwrap();
+            for config in [config, limited] {
+                let results: Vec<_> = ENGINES
+                    .iter()
+                    .map(|name| {
+                        let engine = by_name(name).unwrap();
+                        engine.execute(&program, &config, &mut io::empty(), &mut io::sink())
+                    })
+                    .collect();
+                assert!(results[0].status.is_err(), "{codes}");
+                assert_eq!(results[0], results[1], "{codes}");
+            }
+        }
+    }
+}
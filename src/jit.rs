@@ -1,14 +1,65 @@
 use crate::bytecode::Inst;
-use crate::vm::EOF;
+use crate::token::Span;
 use crate::vm::MEMSIZE;
+use crate::vm::{dump, Program, EOF};
 use std::arch::asm;
-use std::{error, fmt, ptr};
+use std::time::Instant;
+use std::{error, fmt, io, ptr};
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use libc::c_void;
 
-fn codegen(bytecodes: &[Inst]) -> Result<Vec<u8>, CogenError> {
+mod asm;
+mod elf;
+
+use self::asm::{disp, Assembler};
+pub use self::elf::elf;
+
+// what the code does with the steps in r15
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Counting {
+    NONE,
+    // counting them down from 0
+    COUNTED,
+    // with the checks of Budget, also stopping when the output is cut
+    LIMITED,
+}
+
+// the code of the whole program, entered at the pc `entry`, which may be in a loop
+fn codegen(bytecodes: &[Inst], entry: usize, counting: Counting) -> Result<Vec<u8>, CogenError> {
+    let mut a = Assembler::new(false);
+    generate(&mut a, bytecodes, &[], entry, counting)?;
+    Ok(a.code)
+}
+
+// the code of the JIT for program as GNU assembler text, each instruction annotated with its pc
+// and its source. `limited` as with a Budget
+pub fn asm(program: &Program, limited: bool) -> Result<String, CogenError> {
+    let counting = if limited {
+        Counting::LIMITED
+    } else {
+        Counting::COUNTED
+    };
+    let mut a = Assembler::new(true);
+    a.comment(format_args!(
+        "generated by bf-jit, called with rdi = the io and rcx = jit_io"
+    ));
+    generate(&mut a, &program.bytecodes, &program.spans, 0, counting)?;
+    Ok(format!(
+        ".intel_syntax noprefix\n{}",
+        a.listing().unwrap_or_default()
+    ))
+}
+
+fn generate(
+    a: &mut Assembler,
+    bytecodes: &[Inst],
+    spans: &[Span],
+    entry: usize,
+    counting: Counting,
+) -> Result<(), CogenError> {
+    let limited = counting == Counting::LIMITED;
     if !(cfg!(target_os = "linux") || cfg!(target_os = "macos")) {
         return Err(CogenError::UnsupportedOS);
     }
@@ -16,237 +67,465 @@ fn codegen(bytecodes: &[Inst]) -> Result<Vec<u8>, CogenError> {
         return Err(CogenError::UnsupportedArch);
     }
 
-    // TODO: mmapする領域に直書き
-    let mut machine_codes = vec![];
-
-    let mut stack_loop = vec![]; // TODO: loop用の構造をparse時点で作る
+    let mut offsets = vec![]; // offsets of the machine code for each instruction
+    let mut jmp_loop = vec![];
     let mut jmp_abort = vec![];
+    let mut jmp_refuel = vec![];
+    let mut jmp_stop = vec![];
+    let mut prints = vec![];
+
+    // the pcs jumped to, labeled as .pc#{pc}
+    let mut targets: BTreeSet<usize> = bytecodes
+        .iter()
+        .filter_map(|inst| match inst {
+            Inst::JZ(addr) | Inst::JNZ(addr) => Some(*addr),
+            _ => None,
+        })
+        .collect();
+    if entry > 0 {
+        targets.insert(entry);
+    }
+    let (charge, after) = charges(bytecodes);
+    let end = bytecodes.len();
 
     // r12: mem + mem_ptr
     // r13: MEMSIZE - 1
     // r14: mem
-    // r15: abort
+    // rax: 0 on return, pc + 1 on abort
+    // r11: the attempted mem_ptr on abort
+    // r15: the steps left until the next refuel, negative when it is due (if limited), or minus
+    // the steps run (if counted)
+    a.comment(format_args!(
+        "r12 = mem + mem_ptr, r13 = MEMSIZE - 1, r14 = mem{}",
+        match counting {
+            Counting::NONE => "",
+            Counting::COUNTED => ", r15 = -the steps run",
+            Counting::LIMITED => ", r15 = the steps left",
+        }
+    ));
 
     //stack alignment(tmp)
-    machine_codes.extend_from_slice(&[0x48, 0x83, 0xEC, 0x08]);
+    a.inst(&[0x48, 0x83, 0xEC, 0x08], format_args!("sub rsp, 8"));
+    let mut jmp_entry = None;
+    if entry > 0 {
+        // the steps of the bodies around entry from it on, less those charged at entry itself
+        let left = after[entry] + 1 - charge[entry];
+        if counting != Counting::NONE && left > 0 {
+            r15(a, "sub", left);
+        }
+        a.inst(
+            &[0xE9, 0xAF, 0xBE, 0xAD, 0xDE],
+            format_args!("{{disp32}} jmp .pc{entry}"),
+        );
+        jmp_entry = Some(a.offset());
+    }
+
+    for (pc, inst) in bytecodes.iter().enumerate() {
+        offsets.push(a.offset());
+        if targets.contains(&pc) {
+            a.label(format_args!(".pc{pc}"));
+        }
+        if counting != Counting::NONE && charge[pc] > 0 {
+            a.comment(format_args!("the steps of the body from here"));
+            r15(a, "sub", charge[pc]);
+        }
+        if limited && charge[pc] > 0 {
+            a.inst(
+                &[0x0F, 0x88, 0xAF, 0xBE, 0xAD, 0xDE],
+                format_args!("{{disp32}} js .refuel{}", jmp_refuel.len()),
+            );
+            a.label(format_args!(".continue{}", jmp_refuel.len()));
+            // stopping before the body, also what is charged after its JZ is not run
+            let back = charge[pc] + if pc == 0 { 0 } else { after[pc - 1] };
+            jmp_refuel.push((a.offset(), pc, back));
+        }
+        match spans.get(pc) {
+            Some(Span { line, col, .. }) => {
+                a.comment(format_args!("{pc}: {inst:?} at {line}:{col}"))
+            }
+            None => a.comment(format_args!("{pc}: {inst:?}")),
+        }
 
-    for inst in bytecodes.iter() {
         match inst {
             Inst::MOVPTR(_v) => {
                 let v = *_v % MEMSIZE as isize;
-
-                // TODO: gen macro
-                if -128 <= v && v <= 127 {
-                    // addq r12, #{v}
-                    machine_codes.extend_from_slice(&[0x49, 0x83, 0xC4, v as u8])
-                } else {
-                    // movabs rax, #{v}
-                    machine_codes.extend_from_slice(&[0x48, 0xB8]);
-                    machine_codes.extend_from_slice(&(v.to_le_bytes()));
-                    // add r12, rax
-                    machine_codes.extend_from_slice(&[0x49, 0x01, 0xC4]);
-                }
-
-                // underflow / overflow
-                // mov rax, r12
-                // sub rax, r14
-                // cmp rax, r13 ; 0 > mem_ptr/*rax*/ || MEMSIZE - 1/*r13*/ < mem_ptr/*rax*/
-                // jbe .abort_mem
-                machine_codes.extend_from_slice(&[
-                    0x4C, 0x89, 0xE0, 0x4C, 0x29, 0xF0, 0x4C, 0x39, 0xE8, 0x0F, 0x87, 0xAF, 0xBE,
-                    0xAD, 0xDE,
-                ]);
-                jmp_abort.push(machine_codes.len());
+                add_imm(a, "r12", v);
+                check(a, "r12", v, &mut jmp_abort, pc);
             }
             Inst::ADD(v) => {
-                // addb [r12], #{v}
-                machine_codes.extend_from_slice(&[0x41, 0x80, 0x04, 0x24, *v as u8])
+                a.inst(
+                    &[0x41, 0x80, 0x04, 0x24, *v as u8],
+                    format_args!("add byte ptr [r12], {}", *v as u8 as i8),
+                );
             }
             Inst::SETZERO => {
-                // movb [r12], 0
-                machine_codes.extend_from_slice(&[0x41, 0xC6, 0x04, 0x24, 0x00])
+                a.inst(
+                    &[0x41, 0xC6, 0x04, 0x24, 0x00],
+                    format_args!("mov byte ptr [r12], 0"),
+                );
+            }
+            Inst::SET(v) => {
+                a.inst(
+                    &[0x41, 0xC6, 0x04, 0x24, *v],
+                    format_args!("mov byte ptr [r12], {v}"),
+                );
             }
             Inst::MULINTO(coef, _offset) => {
                 let offset = *_offset % MEMSIZE as isize;
                 // MEMO: cell sizeはu8なので，-255 <= coef <= 255
 
-                // ; r11 <= mem_ptr_to
-                // mov r11, r12
-                machine_codes.extend_from_slice(&[0x4D, 0x89, 0xE3]);
-
-                if -128 <= offset && offset <= 127 {
-                    // addq r11, #{offset}
-                    machine_codes.extend_from_slice(&[0x49, 0x83, 0xC3, offset as u8])
-                } else {
-                    // movabs rax, #{offset}
-                    // add r11, rax
-                    machine_codes.extend_from_slice(&[0x48, 0xB8]);
-                    machine_codes.extend_from_slice(&(offset.to_le_bytes()));
-                    machine_codes.extend_from_slice(&[0x49, 0x01, 0xC3]);
-                }
+                // r11 <= mem_ptr_to
+                a.inst(&[0x4D, 0x89, 0xE3], format_args!("mov r11, r12"));
+                add_imm(a, "r11", offset);
+                check(a, "r11", 0, &mut jmp_abort, pc);
 
-                // mov rax, r11
-                // sub rax, r14
-                // cmp rax, r13
-                // jbe .abort_mem
-                machine_codes.extend_from_slice(&[
-                    0x4C, 0x89, 0xD8, 0x4C, 0x29, 0xF0, 0x4C, 0x39, 0xE8, 0x0F, 0x87, 0xAF, 0xBE,
-                    0xAD, 0xDE,
-                ]);
-                jmp_abort.push(machine_codes.len());
-
-                // movzxb eax, [r12]
-                // imul eax, eax, #{coef}
-                // addb [r11], al
-                // movb [r12], 0
-                machine_codes.extend_from_slice(&[0x41, 0x0F, 0xB6, 0x04, 0x24, 0x69, 0xC0]);
-                machine_codes.extend_from_slice(&(*coef as i32).to_le_bytes());
-                machine_codes.extend_from_slice(&[0x41, 0x00, 0x03, 0x41, 0xC6, 0x04, 0x24, 0x00]);
+                a.inst(
+                    &[0x41, 0x0F, 0xB6, 0x04, 0x24],
+                    format_args!("movzx eax, byte ptr [r12]"),
+                );
+                a.bytes(
+                    &[&[0x69, 0xC0][..], &(*coef as i32).to_le_bytes()].concat(),
+                    format_args!("imul eax, eax, {coef}"),
+                );
+                a.inst(&[0x41, 0x00, 0x03], format_args!("add byte ptr [r11], al"));
+                a.inst(
+                    &[0x41, 0xC6, 0x04, 0x24, 0x00],
+                    format_args!("mov byte ptr [r12], 0"),
+                );
             }
             Inst::FINDZERO(_v) => {
                 let v = *_v % MEMSIZE as isize;
-                if -128 <= v && v <= 127 {
-                    // s0:
-                    // cmpb [r12], 0x0
-                    // je s1
-                    // addq r12, #{v}
-                    // mov rax, r12
-                    // sub rax, r14
-                    // cmp rax, r13
-                    // jbe .abort_mem
-                    // jmp s0
-                    // s1:
-                    machine_codes.extend_from_slice(&[
-                        0x41, 0x80, 0x3C, 0x24, 0x00, 0x74, 0x15, 0x49, 0x83, 0xC4, v as u8, 0x4C,
-                        0x89, 0xE0, 0x4C, 0x29, 0xF0, 0x4C, 0x39, 0xE8, 0x0F, 0x87, 0xAF, 0xBE,
-                        0xAD, 0xDE,
-                    ]);
-                    jmp_abort.push(machine_codes.len());
-                    machine_codes.extend_from_slice(&[0xEB, 0xE4]);
-                } else {
-                    // s0:
-                    // cmpb [r12], 0x0
-                    // je s1
-                    // movabs rax, #{v}
-                    // add r12, rax
-                    // mov rax, r12
-                    // sub rax, r14
-                    // cmp rax, r13
-                    // jbe .abort_mem
-                    // jmp s0
-                    // s1:
-                    machine_codes
-                        .extend_from_slice(&[0x41, 0x80, 0x3C, 0x24, 0x00, 0x74, 0x1E, 0x48, 0xB8]);
-                    machine_codes.extend_from_slice(&(v.to_le_bytes()));
-                    machine_codes.extend_from_slice(&[
-                        0x49, 0x01, 0xC4, 0x4C, 0x89, 0xE0, 0x4C, 0x29, 0xF0, 0x4C, 0x39, 0xE8,
-                        0x0F, 0x87, 0xAF, 0xBE, 0xAD, 0xDE,
-                    ]);
-                    jmp_abort.push(machine_codes.len());
-                    machine_codes.extend_from_slice(&[0xEB, 0xDB]);
+                let s0 = a.offset();
+                a.label(format_args!(".find{pc}"));
+                a.inst(
+                    &[0x41, 0x80, 0x3C, 0x24, 0x00],
+                    format_args!("cmp byte ptr [r12], 0"),
+                );
+                a.inst(&[0x74, 0x00], format_args!("je .find{pc}_end"));
+                let je_s1 = a.offset();
+                add_imm(a, "r12", v);
+                check(a, "r12", v, &mut jmp_abort, pc);
+                a.inst(&[0xEB, 0x00], format_args!("jmp .find{pc}"));
+                a.patch8(a.offset(), s0);
+                a.patch8(je_s1, a.offset());
+                a.label(format_args!(".find{pc}_end"));
+            }
+            Inst::SCAN(adds, _step) => {
+                let step = *_step % MEMSIZE as isize;
+
+                let s0 = a.offset();
+                a.label(format_args!(".scan{pc}"));
+                a.inst(
+                    &[0x41, 0x80, 0x3C, 0x24, 0x00],
+                    format_args!("cmp byte ptr [r12], 0"),
+                );
+                a.inst(
+                    &[0x0F, 0x84, 0xAF, 0xBE, 0xAD, 0xDE],
+                    format_args!("{{disp32}} je .scan{pc}_end"),
+                );
+                let je_s1 = a.offset();
+
+                for &(_offset, v) in adds.iter() {
+                    let offset = _offset % MEMSIZE as isize;
+                    a.inst(
+                        &[
+                            &[0x4D, 0x8D, 0x9C, 0x24][..],
+                            &(offset as i32).to_le_bytes(),
+                        ]
+                        .concat(),
+                        format_args!("{{disp32}} lea r11, [r12{}]", disp(offset)),
+                    );
+                    check(a, "r11", 0, &mut jmp_abort, pc);
+                    a.inst(
+                        &[0x41, 0x80, 0x03, v as u8],
+                        format_args!("add byte ptr [r11], {}", v as u8 as i8),
+                    );
                 }
+
+                a.bytes(
+                    &[&[0x49, 0x81, 0xC4][..], &(step as i32).to_le_bytes()].concat(),
+                    format_args!("add r12, {step}"),
+                );
+                check(a, "r12", step, &mut jmp_abort, pc);
+                a.inst(
+                    &[0xE9, 0xAF, 0xBE, 0xAD, 0xDE],
+                    format_args!("{{disp32}} jmp .scan{pc}"),
+                );
+                a.patch(a.offset(), s0);
+                a.patch(je_s1, a.offset());
+                a.label(format_args!(".scan{pc}_end"));
             }
             Inst::PUTC => {
-                // push rdi
-                // push rcx
-                // mov rsi, 1
-                // mov rdx, r12
-                // call rcx
-                // pop rcx
-                // pop rdi
-
-                machine_codes.extend_from_slice(&[
-                    0x57, 0x51, 0x48, 0xC7, 0xC6, 0x01, 0x00, 0x00, 0x00, 0x4C, 0x89, 0xE2, 0xFF,
-                    0xD1, 0x59, 0x5F,
-                ]);
+                a.inst(&[0x57], format_args!("push rdi"));
+                a.inst(&[0x51], format_args!("push rcx"));
+                a.inst(
+                    &[0x48, 0xC7, 0xC6, 0x01, 0x00, 0x00, 0x00],
+                    format_args!("mov rsi, 1"),
+                );
+                a.inst(&[0x4C, 0x89, 0xE2], format_args!("mov rdx, r12"));
+                a.inst(&[0xFF, 0xD1], format_args!("call rcx"));
+                a.inst(&[0x59], format_args!("pop rcx"));
+                a.inst(&[0x5F], format_args!("pop rdi"));
+                if limited {
+                    stop_if_cut(a, &mut jmp_stop, pc);
+                }
+            }
+            Inst::PRINT(s) => {
+                a.inst(&[0x57], format_args!("push rdi"));
+                a.inst(&[0x51], format_args!("push rcx"));
+                a.inst(&[0x48, 0x89, 0xC8], format_args!("mov rax, rcx"));
+                a.inst(&[0xBE, 0x02, 0x00, 0x00, 0x00], format_args!("mov esi, 2"));
+                a.inst(
+                    &[0x48, 0x8D, 0x15, 0xAF, 0xBE, 0xAD, 0xDE],
+                    format_args!("lea rdx, [rip + .str{}]", prints.len()),
+                );
+                prints.push((a.offset(), s));
+                a.inst(
+                    &[&[0x48, 0xC7, 0xC1][..], &(s.len() as u32).to_le_bytes()].concat(),
+                    format_args!("mov rcx, {}", s.len()),
+                );
+                a.inst(&[0xFF, 0xD0], format_args!("call rax"));
+                a.inst(&[0x59], format_args!("pop rcx"));
+                a.inst(&[0x5F], format_args!("pop rdi"));
+                if limited {
+                    stop_if_cut(a, &mut jmp_stop, pc);
+                }
             }
             Inst::GETC => {
-                // push rdi
-                // push rcx
-                // mov rsi, 0
-                // mov rdx, r12
-                // call rcx
-                // movb [r12], al
-                // pop rcx
-                // pop rdi
-
-                machine_codes.extend_from_slice(&[
-                    0x57, 0x51, 0x48, 0xC7, 0xC6, 0x00, 0x00, 0x00, 0x00, 0x4C, 0x89, 0xE2, 0xFF,
-                    0xD1, 0x41, 0x88, 0x04, 0x24, 0x59, 0x5F,
-                ]);
+                a.inst(&[0x57], format_args!("push rdi"));
+                a.inst(&[0x51], format_args!("push rcx"));
+                a.inst(
+                    &[0x48, 0xC7, 0xC6, 0x00, 0x00, 0x00, 0x00],
+                    format_args!("mov rsi, 0"),
+                );
+                a.inst(&[0x4C, 0x89, 0xE2], format_args!("mov rdx, r12"));
+                a.inst(&[0xFF, 0xD1], format_args!("call rcx"));
+                a.inst(
+                    &[0x41, 0x88, 0x04, 0x24],
+                    format_args!("mov byte ptr [r12], al"),
+                );
+                a.inst(&[0x59], format_args!("pop rcx"));
+                a.inst(&[0x5F], format_args!("pop rdi"));
             }
-            Inst::JZ(_) => {
-                // cmpb [r12], 0x0
-                machine_codes.extend_from_slice(&[0x41, 0x80, 0x3C, 0x24, 0x00]);
-
-                // TODO: loop用の構造をbytecode生成時点で作る
-                stack_loop.push(machine_codes.len());
-
-                // je #{placeholder}
-                machine_codes.extend_from_slice(&[0x0F, 0x84, 0xAF, 0xBE, 0xAD, 0xDE]);
+            Inst::DEBUG => {
+                a.inst(&[0x57], format_args!("push rdi"));
+                a.inst(&[0x51], format_args!("push rcx"));
+                a.inst(&[0xBE, 0x03, 0x00, 0x00, 0x00], format_args!("mov esi, 3"));
+                a.inst(&[0x4C, 0x89, 0xE2], format_args!("mov rdx, r12"));
+                a.inst(&[0x48, 0x89, 0xC8], format_args!("mov rax, rcx"));
+                a.inst(&[0x4C, 0x89, 0xE1], format_args!("mov rcx, r12"));
+                a.inst(&[0x4C, 0x29, 0xF1], format_args!("sub rcx, r14"));
+                a.inst(&[0xFF, 0xD0], format_args!("call rax"));
+                a.inst(&[0x59], format_args!("pop rcx"));
+                a.inst(&[0x5F], format_args!("pop rdi"));
             }
-            Inst::JNZ(_) => {
-                // cmpb [r12], 0x0
-                machine_codes.extend_from_slice(&[0x41, 0x80, 0x3C, 0x24, 0x00]);
-                let loop_start_offset = (stack_loop.pop().unwrap() + 6) as u32;
-                let loop_end_offset = (machine_codes.len() + 6) as u32;
-
-                // jne #{loop_start}
-                machine_codes.extend_from_slice(&[0x0F, 0x85]);
-
-                machine_codes.extend_from_slice(
-                    &(loop_start_offset as i32 - loop_end_offset as i32).to_le_bytes(),
+            Inst::JZ(addr) => {
+                a.inst(
+                    &[0x41, 0x80, 0x3C, 0x24, 0x00],
+                    format_args!("cmp byte ptr [r12], 0"),
                 );
-
-                for (i, &bt) in (loop_end_offset - loop_start_offset)
-                    .to_le_bytes()
-                    .iter()
-                    .enumerate()
-                {
-                    machine_codes[loop_start_offset as usize - 4 + i] = bt;
-                }
+                a.inst(
+                    &[0x0F, 0x84, 0xAF, 0xBE, 0xAD, 0xDE],
+                    format_args!("{{disp32}} je .pc{addr}"),
+                );
+                jmp_loop.push((a.offset(), *addr));
+            }
+            Inst::JNZ(addr) => {
+                a.inst(
+                    &[0x41, 0x80, 0x3C, 0x24, 0x00],
+                    format_args!("cmp byte ptr [r12], 0"),
+                );
+                a.inst(
+                    &[0x0F, 0x85, 0xAF, 0xBE, 0xAD, 0xDE],
+                    format_args!("{{disp32}} jne .pc{addr}"),
+                );
+                a.patch(a.offset(), offsets[*addr]);
             }
         }
     }
+    offsets.push(a.offset());
+    if targets.contains(&end) {
+        a.label(format_args!(".pc{end}"));
+    }
+
+    // the targets of JZ are after the loop
+    for &(j_from, target) in jmp_loop.iter() {
+        a.patch(j_from, offsets[target]);
+    }
+    if let Some(j_from) = jmp_entry {
+        a.patch(j_from, offsets[entry]);
+    }
 
-    // add rsp, 0x8
-    // ret
-    machine_codes.extend_from_slice(&[0x48, 0x83, 0xC4, 0x08, 0xc3]);
+    a.comment(format_args!("the end"));
+    a.inst(&[0x31, 0xC0], format_args!("xor eax, eax"));
+    a.inst(&[0x48, 0x83, 0xC4, 0x08], format_args!("add rsp, 8"));
+    a.inst(&[0xC3], format_args!("ret"));
+
+    for (n, &(j_from, pc, back)) in jmp_abort.iter().enumerate() {
+        a.patch(j_from, a.offset());
+        a.label(format_args!(".abort{n}"));
+        a.inst(
+            &[0x49, 0x89, 0xC3],
+            format_args!("mov r11, rax # the attempted mem_ptr"),
+        );
+        if back != 0 {
+            // the pointer stays in the tape as in the VM
+            add_imm(a, "r12", -back);
+        }
+        if counting != Counting::NONE && after[pc] > 0 {
+            r15(a, "add", after[pc]);
+        }
+        a.inst(
+            &[&[0xB8][..], &((pc + 1) as u32).to_le_bytes()].concat(),
+            format_args!("mov eax, {}", pc + 1),
+        );
+        a.inst(&[0x48, 0x83, 0xC4, 0x08], format_args!("add rsp, 8"));
+        a.inst(&[0xC3], format_args!("ret"));
+    }
 
-    let j_to = machine_codes.len();
-    for &j_from in jmp_abort.iter() {
-        for (i, &bt) in (j_to as u32 - j_from as u32)
-            .to_le_bytes()
-            .iter()
-            .enumerate()
-        {
-            machine_codes[j_from - 4 + i] = bt;
+    // the output was cut by the instruction at pc
+    for (n, &(j_from, pc)) in jmp_stop.iter().enumerate() {
+        a.patch(j_from, a.offset());
+        a.label(format_args!(".cut{n}"));
+        if after[pc] > 0 {
+            r15(a, "add", after[pc]);
         }
+        a.inst(
+            &[&[0xB8][..], &((pc + 1) as u32).to_le_bytes()].concat(),
+            format_args!("mov eax, {}", pc + 1),
+        );
+        a.inst(&[0x48, 0x83, 0xC4, 0x08], format_args!("add rsp, 8"));
+        a.inst(&[0xC3], format_args!("ret"));
     }
 
-    // .abort_mem:
-    // xor rdi, rdi
-    // call r15
-    machine_codes.extend_from_slice(&[0x48, 0x31, 0xFF, 0x41, 0xFF, 0xD7]);
-
-    if cfg!(debug_assertions) {
-        let dump = || -> Result<(), std::io::Error> {
-            use std::io::Write;
-            let mut f = std::fs::OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open("dump")?;
-            f.write_all(&machine_codes[..])?;
-            f.flush()?;
-            Ok(())
-        };
-        if let Err(e) = dump() {
-            eprintln!("Error(debug): {e}");
+    // add the steps given by jit_io until the run at pc is paid, or stop before it
+    for (n, &(j_from, pc, back)) in jmp_refuel.iter().enumerate() {
+        a.patch(j_from, a.offset());
+        let refuel = a.offset();
+        a.label(format_args!(".refuel{n}"));
+        a.inst(&[0x57], format_args!("push rdi"));
+        a.inst(&[0x51], format_args!("push rcx"));
+        a.inst(&[0xBE, 0x04, 0x00, 0x00, 0x00], format_args!("mov esi, 4"));
+        a.inst(&[0x48, 0x89, 0xC8], format_args!("mov rax, rcx"));
+        a.inst(&[0xFF, 0xD0], format_args!("call rax"));
+        a.inst(&[0x59], format_args!("pop rcx"));
+        a.inst(&[0x5F], format_args!("pop rdi"));
+        a.inst(&[0x48, 0x85, 0xC0], format_args!("test rax, rax"));
+        a.inst(&[0x74, 0x00], format_args!("jz .stop{n}"));
+        let jz_stop = a.offset();
+        a.inst(&[0x49, 0x01, 0xC7], format_args!("add r15, rax"));
+        a.inst(&[0x78, 0x00], format_args!("js .refuel{n}"));
+        a.patch8(a.offset(), refuel);
+        a.inst(
+            &[0xE9, 0xAF, 0xBE, 0xAD, 0xDE],
+            format_args!("{{disp32}} jmp .continue{n}"),
+        );
+        a.patch(a.offset(), j_from);
+        a.patch8(jz_stop, a.offset());
+        a.label(format_args!(".stop{n}"));
+        r15(a, "add", back);
+        a.inst(
+            &[&[0xB8][..], &((pc + 1) as u32).to_le_bytes()].concat(),
+            format_args!("mov eax, {}", pc + 1),
+        );
+        a.inst(&[0x48, 0x83, 0xC4, 0x08], format_args!("add rsp, 8"));
+        a.inst(&[0xC3], format_args!("ret"));
+    }
+
+    // data for PRINT
+    for (n, &(lea_end, s)) in prints.iter().enumerate() {
+        a.patch(lea_end, a.offset());
+        a.label(format_args!(".str{n}"));
+        a.data(s);
+    }
+    Ok(())
+}
+
+// add v to reg, r12 or r11
+fn add_imm(a: &mut Assembler, reg: &str, v: isize) {
+    let modrm = if reg == "r11" { 0xC3 } else { 0xC4 };
+    if (-128..=127).contains(&v) {
+        a.inst(
+            &[0x49, 0x83, modrm, v as u8],
+            format_args!("add {reg}, {v}"),
+        );
+    } else {
+        a.inst(
+            &[&[0x48, 0xB8][..], &v.to_le_bytes()].concat(),
+            format_args!("movabs rax, {v}"),
+        );
+        a.inst(&[0x49, 0x01, modrm], format_args!("add {reg}, rax"));
+    }
+}
+
+// the steps charged at once for the instructions run as many times as each other: before the top
+// level, and on entering a loop body (with its JNZ) or an if body. `charge[i]` is charged before
+// bytecodes[i], and `after[i]` is charged but not run yet when bytecodes[i] runs, to give back
+// when stopping there
+fn charges(bytecodes: &[Inst]) -> (Vec<usize>, Vec<usize>) {
+    let n = bytecodes.len();
+    let mut charge = vec![0; n + 1];
+    // the start of the body of each instruction, and how many of the body come before it
+    let mut place = vec![(0, 0); n];
+    // the starts and the ends of the bodies around i, the top level first
+    let mut open = vec![(0, n)];
+    for (i, inst) in bytecodes.iter().enumerate() {
+        while open.last().map_or(false, |&(_, end)| end == i) {
+            open.pop();
         }
+        let start = open.last().map_or(0, |&(start, _)| start);
+        place[i] = (start, charge[start]);
+        charge[start] += 1;
+        if let Inst::JZ(exit) = inst {
+            open.push((i + 1, *exit));
+        }
+    }
+    let mut after = vec![0; n];
+    for (i, &(start, before)) in place.iter().enumerate() {
+        let outer = if start == 0 { 0 } else { after[start - 1] };
+        after[i] = charge[start] - before - 1 + outer;
     }
+    (charge, after)
+}
+
+// sub or add v to r15
+fn r15(a: &mut Assembler, op: &str, v: usize) {
+    let modrm = if op == "sub" { 0xEF } else { 0xC7 };
+    a.bytes(
+        &[&[0x49, 0x81, modrm][..], &(v as u32).to_le_bytes()].concat(),
+        format_args!("{op} r15, {v}"),
+    );
+}
+
+// stop at pc if jit_io returned non-zero for a write cut by the output limit
+fn stop_if_cut(a: &mut Assembler, jmp_stop: &mut Vec<(usize, usize)>, pc: usize) {
+    a.inst(&[0x48, 0x85, 0xC0], format_args!("test rax, rax"));
+    a.inst(
+        &[0x0F, 0x85, 0xAF, 0xBE, 0xAD, 0xDE],
+        format_args!("{{disp32}} jne .cut{}", jmp_stop.len()),
+    );
+    jmp_stop.push((a.offset(), pc));
+}
 
-    Ok(machine_codes)
+// jump to a new abort of pc if the address in reg, r12 or r11, is outside the tape, where r12 is
+// moved back by `back`
+fn check(
+    a: &mut Assembler,
+    reg: &str,
+    back: isize,
+    jmp_abort: &mut Vec<(usize, usize, isize)>,
+    pc: usize,
+) {
+    let modrm = if reg == "r11" { 0xD8 } else { 0xE0 };
+    a.inst(&[0x4C, 0x89, modrm], format_args!("mov rax, {reg}"));
+    a.inst(&[0x4C, 0x29, 0xF0], format_args!("sub rax, r14"));
+    // 0 > mem_ptr || MEMSIZE - 1 < mem_ptr as unsigned
+    a.inst(&[0x4C, 0x39, 0xE8], format_args!("cmp rax, r13"));
+    a.inst(
+        &[0x0F, 0x87, 0xAF, 0xBE, 0xAD, 0xDE],
+        format_args!("{{disp32}} ja .abort{}", jmp_abort.len()),
+    );
+    jmp_abort.push((a.offset(), pc, back));
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -292,41 +571,83 @@ impl MachineCodePage {
         Self { mem, size }
     }
 
-    pub unsafe fn new_from_bytecode(bytecodes: &[Inst]) -> Self {
+    #[allow(dead_code)]
+    pub unsafe fn new_from_bytecode(_bytecodes: &[Inst]) -> Self {
         unimplemented!();
     }
 
     pub unsafe fn pre_exec(&self) {
-        libc::mprotect(self.mem, self.size, libc::PROT_EXEC);
+        libc::mprotect(self.mem, self.size, libc::PROT_READ | libc::PROT_EXEC);
     }
 
     pub unsafe fn post_exec(&self) {
         libc::mprotect(self.mem, self.size, libc::PROT_READ);
     }
 
-    fn merge(page: MachineCodePage) {
+    #[allow(dead_code)]
+    fn merge(_page: MachineCodePage) {
         unimplemented!();
     }
 }
 
-extern "C" fn jit_abort(error_code: u8) {
-    match error_code {
-        0 => eprintln!("Error: memory out of range"),
-        _ => (),
-    }
-    std::process::exit(1);
-}
-
-extern "C" fn jit_io(io: &mut IO, c: u8, buf: &mut u8) -> u8 {
+// c: 0 = read, 1 = write a byte, 2 = write `len` bytes, returning non-zero if the output limit
+// cut it, 3 = dump the tape with `buf` at mem_ptr `len`, 4 = refuel, returning the next steps to
+// run or 0 to stop
+extern "C" fn jit_io(io: &mut IO, c: u8, buf: *mut u8, len: usize) -> usize {
     if c == 0 {
-        return io.read();
+        return io.read() as usize;
+    } else if c == 4 {
+        return io.refuel();
     } else if c == 1 {
-        io.write(buf);
+        return io.write(unsafe { std::slice::from_raw_parts(buf, 1) }) as usize;
+    } else if c == 2 {
+        return io.write(unsafe { std::slice::from_raw_parts(buf, len) }) as usize;
+    } else if c == 3 {
+        let mem = unsafe { std::slice::from_raw_parts(buf.sub(len), MEMSIZE) };
+        io.flush();
+        eprintln!("{}", dump(mem, len));
     }
-    return 0;
+    0
+}
+
+#[derive(Debug)]
+pub enum Abort {
+    // the instruction at pc moved the pointer to ptr, out of the tape, from mem_ptr
+    Memory {
+        pc: usize,
+        mem_ptr: usize,
+        ptr: isize,
+    },
+    // stopped before the instruction at pc by the budget, or by the output limit at it, with the
+    // pointer at mem_ptr
+    Limit {
+        pc: usize,
+        mem_ptr: usize,
+        limit: Limit,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Steps,
+    Time,
+    Output,
+}
+
+// steps handed out per refuel, which is also how often the deadline is checked
+const REFUEL_STEPS: usize = 1 << 14;
+
+// what is left of the limits of the VM
+#[derive(Debug, Default)]
+pub struct Budget {
+    pub steps: Option<u64>,
+    pub deadline: Option<Instant>,
+    pub output: Option<usize>,
+    pub exceeded: Option<Limit>,
 }
 
 pub struct JIT {
+    #[allow(dead_code)]
     pages: BTreeMap<usize, (usize, MachineCodePage)>,
 }
 
@@ -343,41 +664,41 @@ impl JIT {
         end: usize,
         mem: &[u8],
         mem_ptr: usize,
-    ) -> usize {
-        let pages = self.gen_page(bytecodes, start, end); // TODO
+        io: &mut IO,
+    ) -> Result<usize, Abort> {
+        let counting = match io.budget {
+            Some(_) => Counting::LIMITED,
+            None => Counting::COUNTED,
+        };
+        let pages = self.gen_page(bytecodes, start, end, counting); // TODO
 
         for page in pages.iter() {
             page.pre_exec();
         }
 
-        let mut next_mem_ptr;
+        let status: usize;
+        let mem_end: usize;
+        let attempted: usize;
+        let left: usize;
 
         let mem_start = mem.as_ptr() as usize;
         let mem_cur = mem_start + mem_ptr;
         let page_top_addr = pages[0].mem as usize;
 
-        let abort_addr = jit_abort as usize;
-
-        let mut io = IO {
-            writer: &mut std::io::stdout(),
-            reader: &mut std::io::stdin(),
-        };
-        let io_ptr = &mut io as *mut IO;
-        let jit_io_addr = jit_io as usize;
+        let io_ptr = io as *mut IO;
+        let jit_io_addr = jit_io as *const () as usize;
 
         asm!(
             "call {0}",
-            "sub r12, r14",
-            "mov rax, r12",
             in(reg) page_top_addr,
-            out("rax") next_mem_ptr,
+            out("rax") status,
             in("rdi") io_ptr,
             in("rcx")  jit_io_addr,
-            out("r11") _,
-            inout("r12") mem_cur => _,
+            out("r11") attempted,
+            inout("r12") mem_cur => mem_end,
             inout("r13") MEMSIZE - 1 => _,
             inout("r14") mem_start => _,
-            inout("r15") abort_addr => _,
+            inout("r15") 0usize => left, // refuel at the first run
             clobber_abi("C"), // TODO
         );
 
@@ -385,7 +706,22 @@ impl JIT {
             page.post_exec();
         }
 
-        next_mem_ptr
+        // the steps given less the ones left, which are negative if only counted
+        io.steps = io.steps.wrapping_sub(left as u64);
+        let exceeded = io.budget.as_mut().and_then(|budget| budget.exceeded.take());
+        match (status, exceeded) {
+            (0, _) => Ok(mem_end - mem_start),
+            (_, Some(limit)) => Err(Abort::Limit {
+                pc: status - 1,
+                mem_ptr: mem_end - mem_start,
+                limit,
+            }),
+            _ => Err(Abort::Memory {
+                pc: status - 1,
+                mem_ptr: mem_end - mem_start,
+                ptr: attempted as isize,
+            }),
+        }
     }
 
     unsafe fn gen_page(
@@ -393,10 +729,11 @@ impl JIT {
         bytecodes: &[Inst],
         start: usize,
         end: usize,
+        counting: Counting,
     ) -> Vec<MachineCodePage> {
         // TODO: 機械語のvec生成とcopyが無駄なのでmmapした領域に直接書き込みたい
         // TODO: 既にページが存在するならよしなにやる
-        let machine_codes = codegen(&bytecodes[start..end + 1]).unwrap(); // TODO
+        let machine_codes = codegen(&bytecodes[..end + 1], start, counting).unwrap(); // TODO
 
         let page = MachineCodePage::new(&machine_codes);
         vec![page]
@@ -410,14 +747,18 @@ struct TestIO<'a, R: 'a + std::io::Read, W: 'a + std::io::Write> {
 }
 */
 // TODO
-struct IO<'a> {
-    writer: &'a mut std::io::Stdout,
-    reader: &'a mut std::io::Stdin,
+// the reader and writer of the VM, passed to jit_io
+pub struct IO<'a> {
+    pub writer: &'a mut dyn io::Write,
+    pub reader: &'a mut dyn io::Read,
+    pub budget: Option<Budget>,
+    // the steps given to the code, and after JIT::enter the ones it ran
+    pub steps: u64,
+    pub output: usize, // bytes written
 }
 
 impl IO<'_> {
     fn read(&mut self) -> u8 {
-        use std::io::Read;
         let mut buf: u8 = 0;
         if self
             .reader
@@ -428,8 +769,58 @@ impl IO<'_> {
         }
         buf
     }
-    fn write(&mut self, buf: &mut u8) {
-        use std::io::Write;
-        _ = self.writer.write(std::slice::from_mut(buf));
+    // whether the output limit cut buf
+    fn write(&mut self, buf: &[u8]) -> bool {
+        let mut buf = buf;
+        let mut cut = false;
+        if let Some(budget) = &mut self.budget {
+            if let Some(left) = &mut budget.output {
+                if buf.len() > *left {
+                    buf = &buf[..*left];
+                    budget.exceeded.get_or_insert(Limit::Output);
+                    cut = true;
+                }
+                *left -= buf.len();
+            }
+        }
+        _ = self.writer.write_all(buf);
+        self.output += buf.len();
+        cut
+    }
+    fn refuel(&mut self) -> usize {
+        let steps = self.fuel();
+        self.steps += steps as u64;
+        steps
+    }
+    fn fuel(&mut self) -> usize {
+        let budget = match &mut self.budget {
+            Some(budget) => budget,
+            None => return REFUEL_STEPS,
+        };
+        if budget.exceeded.is_some() {
+            return 0;
+        }
+        if budget
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            budget.exceeded = Some(Limit::Time);
+            return 0;
+        }
+        match &mut budget.steps {
+            Some(0) => {
+                budget.exceeded = Some(Limit::Steps);
+                0
+            }
+            Some(left) => {
+                let steps = (*left).min(REFUEL_STEPS as u64);
+                *left -= steps;
+                steps as usize
+            }
+            None => REFUEL_STEPS,
+        }
+    }
+    fn flush(&mut self) {
+        _ = self.writer.flush();
     }
 }
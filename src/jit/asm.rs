@@ -0,0 +1,202 @@
+use crate::emit;
+use std::fmt::{self, Write};
+
+// machine code, and with a listing the same code as GNU assembler text in Intel syntax. the text
+// is only formatted for a listing, so that the JIT does not pay for it
+pub struct Assembler {
+    pub code: Vec<u8>,
+    listing: Option<String>,
+}
+
+impl Assembler {
+    pub fn new(listing: bool) -> Self {
+        Self {
+            code: vec![],
+            listing: if listing { Some(String::new()) } else { None },
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.code.len()
+    }
+
+    // one instruction encoded as bytes
+    pub fn inst(&mut self, bytes: &[u8], text: fmt::Arguments) {
+        self.code.extend_from_slice(bytes);
+        if let Some(listing) = &mut self.listing {
+            let _ = writeln!(listing, "    {text}");
+        }
+    }
+
+    // the same as .byte lines, for an encoding as would shorten, e.g. an imm32 fitting in an imm8
+    pub fn bytes(&mut self, bytes: &[u8], text: fmt::Arguments) {
+        self.code.extend_from_slice(bytes);
+        if let Some(listing) = &mut self.listing {
+            let bytes: Vec<_> = bytes.iter().map(|b| format!("{b:#04x}")).collect();
+            let _ = writeln!(listing, "    .byte {} # {text}", bytes.join(", "));
+        }
+    }
+
+    pub fn label(&mut self, name: fmt::Arguments) {
+        if let Some(listing) = &mut self.listing {
+            let _ = writeln!(listing, "{name}:");
+        }
+    }
+
+    pub fn comment(&mut self, text: fmt::Arguments) {
+        if let Some(listing) = &mut self.listing {
+            let _ = writeln!(listing, "    # {text}");
+        }
+    }
+
+    pub fn data(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+        if let Some(listing) = &mut self.listing {
+            let octal = |b| format!("\\{b:03o}");
+            let _ = writeln!(
+                listing,
+                "    .ascii \"{}\"",
+                emit::escape(bytes, b"\"\\", octal)
+            );
+        }
+    }
+
+    // point the rel32 ending at `end` to `target`
+    pub fn patch(&mut self, end: usize, target: usize) {
+        let rel = (target as i32).wrapping_sub(end as i32);
+        self.code[end - 4..end].copy_from_slice(&rel.to_le_bytes());
+    }
+
+    // the same for a rel8
+    pub fn patch8(&mut self, end: usize, target: usize) {
+        self.code[end - 1] = (target as isize - end as isize) as i8 as u8;
+    }
+
+    pub fn listing(self) -> Option<String> {
+        self.listing
+    }
+}
+
+// " + v" or " - v" after a register in an address
+pub fn disp(v: isize) -> String {
+    format!(" {} {}", if v < 0 { '-' } else { '+' }, v.unsigned_abs())
+}
+
+#[cfg(all(
+    test,
+    any(target_os = "linux", target_os = "macos"),
+    target_arch = "x86_64"
+))]
+mod tests {
+    use super::super::{asm, codegen, Counting};
+    use crate::bytecode::Inst::*;
+    use crate::token::Span;
+    use crate::vm::Program;
+    use std::{env, fs, process};
+
+    #[test]
+    fn asm_listing() {
+        // "+[>-]" with the loop found as FINDZERO
+        let program = Program {
+            bytecodes: vec![ADD(1), JZ(4), FINDZERO(1), JNZ(2)],
+            spans: (0..4)
+                .map(|i| Span {
+                    start: i,
+                    end: i + 1,
+                    line: 1,
+                    col: i + 1,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        assert_eq!(
+            ".intel_syntax noprefix
+    # generated by bf-jit, called with rdi = the io and rcx = jit_io
+    # r12 = mem + mem_ptr, r13 = MEMSIZE - 1, r14 = mem, r15 = -the steps run
+    sub rsp, 8
+    # the steps of the body from here
+    .byte 0x49, 0x81, 0xef, 0x02, 0x00, 0x00, 0x00 # sub r15, 2
+    # 0: ADD(1) at 1:1
+    add byte ptr [r12], 1
+    # 1: JZ(4) at 1:2
+    cmp byte ptr [r12], 0
+    {disp32} je .pc4
+.pc2:
+    # the steps of the body from here
+    .byte 0x49, 0x81, 0xef, 0x02, 0x00, 0x00, 0x00 # sub r15, 2
+    # 2: FINDZERO(1) at 1:3
+.find2:
+    cmp byte ptr [r12], 0
+    je .find2_end
+    add r12, 1
+    mov rax, r12
+    sub rax, r14
+    cmp rax, r13
+    {disp32} ja .abort0
+    jmp .find2
+.find2_end:
+    # 3: JNZ(2) at 1:4
+    cmp byte ptr [r12], 0
+    {disp32} jne .pc2
+.pc4:
+    # the end
+    xor eax, eax
+    add rsp, 8
+    ret
+.abort0:
+    mov r11, rax # the attempted mem_ptr
+    add r12, -1
+    .byte 0x49, 0x81, 0xc7, 0x01, 0x00, 0x00, 0x00 # add r15, 1
+    mov eax, 3
+    add rsp, 8
+    ret
+",
+            asm(&program, false).unwrap()
+        );
+    }
+
+    // the listing assembled by as is the machine code, if there are as and objcopy
+    #[test]
+    fn asm_reassembles() {
+        for (name, codes) in [
+            ("mandelbrot", include_str!("../../examples/mandelbrot.bf")),
+            ("hello_world", include_str!("../../examples/hello_world.bf")),
+            ("reverse", include_str!("../../examples/reverse.bf")),
+        ] {
+            let program = crate::compile(codes, &Default::default()).unwrap();
+            for limited in [false, true] {
+                let path = |ext: &str| {
+                    env::temp_dir().join(format!("bf-jit-{}-{name}-{limited}.{ext}", process::id()))
+                };
+                fs::write(path("s"), asm(&program, limited).unwrap()).unwrap();
+                let status = process::Command::new("as")
+                    .arg("--64")
+                    .arg("-o")
+                    .args([path("o"), path("s")])
+                    .status();
+                fs::remove_file(path("s")).unwrap();
+                match status {
+                    Ok(status) => assert!(status.success()),
+                    Err(_) => return,
+                }
+                let status = process::Command::new("objcopy")
+                    .args(["-O", "binary", "--only-section=.text"])
+                    .args([path("o"), path("bin")])
+                    .status();
+                fs::remove_file(path("o")).unwrap();
+                match status {
+                    Ok(status) => assert!(status.success()),
+                    Err(_) => return,
+                }
+                let text = fs::read(path("bin")).unwrap();
+                fs::remove_file(path("bin")).unwrap();
+                let counting = if limited {
+                    Counting::LIMITED
+                } else {
+                    Counting::COUNTED
+                };
+                assert_eq!(codegen(&program.bytecodes, 0, counting).unwrap(), text);
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use super::{codegen, CogenError, Counting};
+use crate::emit;
+use crate::vm::{Program, EOF, MEMSIZE};
+
+// where the file and the tape are mapped
+const BASE: usize = 0x400000;
+const PAGE: usize = 0x1000;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+const ABORT_MESSAGE: &[u8] = b"Error: memory out of range (pc ";
+
+// a static x86-64 Linux executable running program with the code of codegen. the tape is in
+// .bss and the io of jit_io is done with raw syscalls, so it needs no libc. a pointer out of the
+// tape exits with 3 as the CLI does, DEBUG does nothing and there are no limits
+pub fn elf(program: &Program) -> Result<Vec<u8>, CogenError> {
+    let headers = EHDR_SIZE + 2 * PHDR_SIZE;
+    let mut text = vec![];
+
+    // _start:
+    // movabs r14, #{tape}
+    // lea r12, [r14 + #{mem_ptr}]
+    // mov r13, #{MEMSIZE - 1}
+    // lea rcx, [rip + .io] ; called by the code in place of jit_io
+    text.extend_from_slice(&[0x49, 0xBE]);
+    let tape_imm = text.len();
+    text.extend_from_slice(&0u64.to_le_bytes());
+    let (mem_ptr, cells) = emit::tape(program);
+    text.extend_from_slice(&[0x4D, 0x8D, 0xA6]);
+    text.extend_from_slice(&(mem_ptr as u32).to_le_bytes());
+    text.extend_from_slice(&[0x49, 0xC7, 0xC5]);
+    text.extend_from_slice(&((MEMSIZE - 1) as u32).to_le_bytes());
+    text.extend_from_slice(&[0x48, 0x8D, 0x0D, 0xAF, 0xBE, 0xAD, 0xDE]);
+    let lea_io = text.len();
+
+    // the tape set by preeval
+    // movb [r14 + #{addr}], #{v}
+    for &(addr, v) in cells.iter() {
+        text.extend_from_slice(&[0x41, 0xC6, 0x86]);
+        text.extend_from_slice(&(addr as u32).to_le_bytes());
+        text.push(v);
+    }
+
+    // call .code
+    // test rax, rax
+    // jne .abort
+    // xor edi, edi
+    // mov eax, 60 ; exit
+    // syscall
+    text.extend_from_slice(&[0xE8, 0xAF, 0xBE, 0xAD, 0xDE]);
+    let call_code = text.len();
+    text.extend_from_slice(&[
+        0x48, 0x85, 0xC0, 0x75, 0x09, 0x31, 0xFF, 0xB8, 0x3C, 0x00, 0x00, 0x00, 0x0F, 0x05,
+    ]);
+
+    // .abort: ; rax = pc + 1, printed below the stack as "#{ABORT_MESSAGE}pc)\n"
+    // dec rax
+    // lea rsi, [rsp - 1]
+    // movw [rsi - 1], 0x0a29
+    // dec rsi
+    // mov ecx, 10
+    // .digit:
+    // xor edx, edx
+    // div rcx
+    // add dl, 0x30
+    // dec rsi
+    // movb [rsi], dl
+    // test rax, rax
+    // jne .digit
+    // mov r8, rsi
+    // mov edi, 2
+    // lea rsi, [rip + #{message}]
+    // mov edx, #{len}
+    // mov eax, 1 ; write
+    // syscall
+    text.extend_from_slice(&[
+        0x48, 0xFF, 0xC8, 0x48, 0x8D, 0x74, 0x24, 0xFF, 0x66, 0xC7, 0x46, 0xFF, 0x29, 0x0A, 0x48,
+        0xFF, 0xCE, 0xB9, 0x0A, 0x00, 0x00, 0x00, 0x31, 0xD2, 0x48, 0xF7, 0xF1, 0x80, 0xC2, 0x30,
+        0x48, 0xFF, 0xCE, 0x88, 0x16, 0x48, 0x85, 0xC0, 0x75, 0xEE, 0x49, 0x89, 0xF0, 0xBF, 0x02,
+        0x00, 0x00, 0x00, 0x48, 0x8D, 0x35, 0xAF, 0xBE, 0xAD, 0xDE,
+    ]);
+    let lea_message = text.len();
+    text.push(0xBA);
+    text.extend_from_slice(&(ABORT_MESSAGE.len() as u32).to_le_bytes());
+    // mov rsi, r8
+    // lea rdx, [rsp - 1]
+    // sub rdx, rsi
+    // inc rdx
+    // mov edi, 2
+    // mov eax, 1 ; write
+    // syscall
+    // mov edi, 3
+    // mov eax, 60 ; exit
+    // syscall
+    text.extend_from_slice(&[
+        0xB8, 0x01, 0x00, 0x00, 0x00, 0x0F, 0x05, 0x4C, 0x89, 0xC6, 0x48, 0x8D, 0x54, 0x24, 0xFF,
+        0x48, 0x29, 0xF2, 0x48, 0xFF, 0xC2, 0xBF, 0x02, 0x00, 0x00, 0x00, 0xB8, 0x01, 0x00, 0x00,
+        0x00, 0x0F, 0x05, 0xBF, 0x03, 0x00, 0x00, 0x00, 0xB8, 0x3C, 0x00, 0x00, 0x00, 0x0F, 0x05,
+    ]);
+
+    // .io: ; esi as c of jit_io, rdx = buf, rcx = len. syscall keeps the registers of the code
+    // test esi, esi
+    // jne .write
+    // mov rsi, rdx
+    // xor edi, edi
+    // mov edx, 1
+    // xor eax, eax ; read
+    // syscall
+    // cmp rax, 1
+    // je .got
+    // movb [rsi], #{EOF}
+    // .got:
+    // movzxb eax, [rsi]
+    // ret
+    let io = text.len();
+    text.extend_from_slice(&[
+        0x85, 0xF6, 0x75, 0x1B, 0x48, 0x89, 0xD6, 0x31, 0xFF, 0xBA, 0x01, 0x00, 0x00, 0x00, 0x31,
+        0xC0, 0x0F, 0x05, 0x48, 0x83, 0xF8, 0x01, 0x74, 0x03, 0xC6, 0x06, EOF, 0x0F, 0xB6, 0x06,
+        0xC3,
+    ]);
+    // .write:
+    // cmp esi, 2
+    // ja .done ; the dump and the refuel
+    // je .many
+    // mov ecx, 1
+    // .many:
+    // mov rsi, rdx
+    // mov rdx, rcx
+    // .again:
+    // mov edi, 1
+    // mov eax, 1 ; write
+    // syscall
+    // test rax, rax
+    // jle .done
+    // add rsi, rax
+    // sub rdx, rax
+    // jne .again
+    // .done:
+    // xor eax, eax
+    // ret
+    text.extend_from_slice(&[
+        0x83, 0xFE, 0x02, 0x77, 0x26, 0x74, 0x05, 0xB9, 0x01, 0x00, 0x00, 0x00, 0x48, 0x89, 0xD6,
+        0x48, 0x89, 0xCA, 0xBF, 0x01, 0x00, 0x00, 0x00, 0xB8, 0x01, 0x00, 0x00, 0x00, 0x0F, 0x05,
+        0x48, 0x85, 0xC0, 0x7E, 0x08, 0x48, 0x01, 0xC6, 0x48, 0x29, 0xC2, 0x75, 0xE7, 0x31, 0xC0,
+        0xC3,
+    ]);
+
+    let message = text.len();
+    text.extend_from_slice(ABORT_MESSAGE);
+
+    let code = text.len();
+    text.extend_from_slice(&codegen(&program.bytecodes, 0, Counting::NONE)?);
+
+    for (from, to) in [(lea_io, io), (call_code, code), (lea_message, message)] {
+        text[from - 4..from].copy_from_slice(&((to as i32 - from as i32) as u32).to_le_bytes());
+    }
+    let size = headers + text.len();
+    let tape = BASE + (size + PAGE - 1) / PAGE * PAGE;
+    text[tape_imm..tape_imm + 8].copy_from_slice(&(tape as u64).to_le_bytes());
+
+    let mut file = Vec::with_capacity(size);
+    // ELF header: 64-bit, little endian, SYSV, an executable for x86-64
+    file.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    put(&mut file, 2, 2); // e_type
+    put(&mut file, 0x3E, 2); // e_machine
+    put(&mut file, 1, 4); // e_version
+    put(&mut file, BASE + headers, 8); // e_entry
+    put(&mut file, EHDR_SIZE, 8); // e_phoff
+    put(&mut file, 0, 8); // e_shoff
+    put(&mut file, 0, 4); // e_flags
+    put(&mut file, EHDR_SIZE, 2); // e_ehsize
+    put(&mut file, PHDR_SIZE, 2); // e_phentsize
+    put(&mut file, 2, 2); // e_phnum
+    put(&mut file, 0, 2); // e_shentsize
+    put(&mut file, 0, 2); // e_shnum
+    put(&mut file, 0, 2); // e_shstrndx
+
+    // the file as read and executable, then the tape as read and write
+    for (flags, addr, filesz, memsz) in [(5, BASE, size, size), (6, tape, 0, MEMSIZE)] {
+        put(&mut file, 1, 4); // p_type: PT_LOAD
+        put(&mut file, flags, 4); // p_flags
+        put(&mut file, 0, 8); // p_offset
+        put(&mut file, addr, 8); // p_vaddr
+        put(&mut file, addr, 8); // p_paddr
+        put(&mut file, filesz, 8); // p_filesz
+        put(&mut file, memsz, 8); // p_memsz
+        put(&mut file, PAGE, 8); // p_align
+    }
+    file.extend_from_slice(&text);
+    Ok(file)
+}
+
+// n as a little endian field of size bytes
+fn put(file: &mut Vec<u8>, n: usize, size: usize) {
+    file.extend_from_slice(&(n as u64).to_le_bytes()[..size]);
+}
+
+#[cfg(all(test, target_os = "linux", target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use crate::vm::TapeInit;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::{env, fs, process};
+
+    // the exit code and the output of program given input
+    fn execute(name: &str, program: &Program, input: &[u8]) -> (Option<i32>, Vec<u8>, Vec<u8>) {
+        let path = env::temp_dir().join(format!("bf-jit-{}-{name}", process::id()));
+        fs::write(&path, elf(program).unwrap()).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut child = process::Command::new(&path)
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        fs::remove_file(&path).unwrap();
+        (output.status.code(), output.stdout, output.stderr)
+    }
+
+    #[test]
+    fn elf_io() {
+        // ",[.,]" then "!" and "\n" from the tape set by preeval
+        let program = Program {
+            bytecodes: vec![
+                GETC,
+                JZ(5),
+                PUTC,
+                GETC,
+                JNZ(2),
+                MOVPTR(1),
+                PRINT(b"!".to_vec()),
+                PUTC,
+            ],
+            spans: vec![],
+            init: Some(TapeInit {
+                mem_ptr: 10,
+                cells: vec![(11, b'\n')],
+                steps: 0,
+            }),
+            ..Default::default()
+        };
+        let (code, stdout, _) = execute("io", &program, b"echo");
+        assert_eq!(Some(0), code);
+        assert_eq!(b"echo!\n".to_vec(), stdout);
+    }
+
+    #[test]
+    fn elf_out_of_range() {
+        let program = Program {
+            bytecodes: vec![ADD(1), MOVPTR(-(MEMSIZE as isize) / 2 - 1)],
+            ..Default::default()
+        };
+        let (code, _, stderr) = execute("out_of_range", &program, b"");
+        assert_eq!(Some(3), code);
+        assert_eq!(b"Error: memory out of range (pc 1)\n".to_vec(), stderr);
+    }
+}
@@ -1,36 +1,337 @@
+#![allow(clippy::upper_case_acronyms)]
+
+use std::sync::Arc;
+use std::time::Instant;
 use std::{error, io};
 
+mod bench;
 mod bytecode;
+mod c;
+mod debugger;
+mod diagnostic;
+mod emit;
+mod engine;
 mod jit;
+mod profile;
 mod token;
+mod trace;
 mod vm;
+mod wasm;
+
+pub use bench::{measure, BenchResults, Benchmark, Measurement, BENCHMARKS};
+pub use bytecode::{CompileError, Inst, OptLevel};
+pub use debugger::HELP as DEBUGGER_HELP;
+pub use diagnostic::snippet;
+pub use engine::{Engine, EngineConfig, Interpreter, Jit, RunResult, ENGINES};
+pub use profile::{InstCost, LoopCost, OptimizationCost, Profile, Report};
+pub use token::{Brainfuck, Dialect, DialectError, Extensions, Span, Words};
+pub use trace::{Divergence, Event, Trace, TraceError};
+pub use vm::{
+    Limits, Program, RuntimeError, Snapshot, SnapshotError, Status, TapeInit, TapeWindow, VM,
+};
+
+pub use vm::PREEVAL_STEPS;
+
+// a built-in engine by its name, one of ENGINES
+pub fn engine(name: &str) -> Option<Arc<dyn Engine>> {
+    engine::by_name(name)
+}
+
+// a built-in benchmark by its name, one of BENCHMARKS
+pub fn benchmark(name: &str) -> Option<Benchmark> {
+    bench::by_name(name)
+}
 
+// a built-in dialect by its name or file extension ("bf", "ook", "blub")
+pub fn dialect(name: &str) -> Option<Arc<dyn Dialect>> {
+    token::by_name(name)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    // Interpreter if None
+    pub engine: Option<Arc<dyn Engine>>,
+    // run the program at compile time until it reads input, up to this many steps
+    pub preeval: Option<usize>,
+    // the syntax of codes, Brainfuck if None
+    pub dialect: Option<Arc<dyn Dialect>>,
+    // "#" and "!"
+    pub extensions: Extensions,
+    pub limits: Limits,
+}
+
+// the run functions return the state the program finished in
 pub fn run<R: io::Read, W: io::Write>(
     codes: &str,
     reader: &mut R,
     writer: &mut W,
-) -> Result<(), Box<dyn error::Error>> {
-    _run(codes, reader, writer, false)
+) -> Result<Snapshot, Box<dyn error::Error>> {
+    run_with_options(codes, reader, writer, &Options::default())
 }
 
 pub fn run_with_jit<R: io::Read, W: io::Write>(
     codes: &str,
     reader: &mut R,
     writer: &mut W,
+) -> Result<Snapshot, Box<dyn error::Error>> {
+    let options = Options {
+        engine: Some(Arc::new(Jit)),
+        ..Default::default()
+    };
+    run_with_options(codes, reader, writer, &options)
+}
+
+pub fn run_with_options<R: io::Read, W: io::Write>(
+    codes: &str,
+    reader: &mut R,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Snapshot, Box<dyn error::Error>> {
+    let start = Instant::now();
+    let (program, inline) = build(codes, options, start)?;
+    let mut reader = io::Read::chain(inline.as_bytes(), reader);
+    let mut vm = VM::for_program(&program);
+    vm.limit_since(options.limits, start);
+    let engine = options.engine.as_deref().unwrap_or(&Interpreter);
+    vm.run(&program, &mut reader, writer, engine)?;
+    Ok(vm.snapshot())
+}
+
+// the Brainfuck codes as they are, one instruction per command but loops, to inspect or to
+// `optimize`
+pub fn parse(codes: &str) -> Result<Program, Box<dyn error::Error>> {
+    let (tokens, _) = tokenize(codes, Extensions::default(), &Options::default())?;
+    Ok(unoptimized(&tokens)?)
+}
+
+fn unoptimized(tokens: &[token::Token]) -> Result<Program, CompileError> {
+    let (bytecodes, spans) = bytecode::lower(bytecode::parse(tokens)?);
+    Ok(Program {
+        bytecodes,
+        spans,
+        ..Default::default()
+    })
+}
+
+// compile codes into a program to run on `VM::for_program(&program)`, e.g. in slices with
+// `VM::run_for`. the input inlined after "!" is not included
+pub fn compile(codes: &str, options: &Options) -> Result<Program, Box<dyn error::Error>> {
+    Ok(build(codes, options, Instant::now())?.0)
+}
+
+// compile codes into a static x86-64 Linux executable that needs neither this crate nor libc.
+// the limits and the input inlined after "!" are not included
+pub fn compile_elf(codes: &str, options: &Options) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let (program, _) = build(codes, options, Instant::now())?;
+    Ok(jit::elf(&program)?)
+}
+
+// the machine code the JIT generates for codes, as GNU assembler text in Intel syntax annotated
+// with the instructions and their source. with limits it has the checks of them
+pub fn compile_asm(codes: &str, options: &Options) -> Result<String, Box<dyn error::Error>> {
+    let (program, _) = build(codes, options, Instant::now())?;
+    Ok(jit::asm(&program, options.limits.any())?)
+}
+
+// translate codes into a self-contained C file, e.g. to build with any C compiler. the limits and
+// the input inlined after "!" are not included
+pub fn compile_c(codes: &str, options: &Options) -> Result<String, Box<dyn error::Error>> {
+    let (program, _) = build(codes, options, Instant::now())?;
+    Ok(c::emit(&program))
+}
+
+// translate codes into a WebAssembly module in the text format, e.g. to run in a browser with
+// getc and putc given by the page. the limits and the input inlined after "!" are not included
+pub fn compile_wat(codes: &str, options: &Options) -> Result<String, Box<dyn error::Error>> {
+    let (program, _) = build(codes, options, Instant::now())?;
+    Ok(wasm::emit(&program))
+}
+
+// run codes without the JIT, and report where the instructions went
+pub fn profile<R: io::Read, W: io::Write>(
+    codes: &str,
+    reader: &mut R,
+    writer: &mut W,
+    options: &Options,
+) -> Result<Report, Box<dyn error::Error>> {
+    let start = Instant::now();
+    let (program, inline) = build(codes, options, start)?;
+    let mut reader = io::Read::chain(inline.as_bytes(), reader);
+    let mut vm = VM::for_program(&program);
+    vm.limit_since(options.limits, start);
+    let profile = profile::profile(&mut vm, &program, &mut reader, writer)?;
+    let commands = |span: Span| {
+        tokenize(&codes[span.start..span.end], Extensions::default(), options)
+            .map_or(0, |(tokens, _)| tokens.len())
+    };
+    Ok(Report::new(&program, &profile, &commands))
+}
+
+// run codes without the JIT, recording the input and the output, and every executed pc with
+// `pcs`, to trace for `replay`
+pub fn record<R: io::Read, W: io::Write>(
+    codes: &str,
+    reader: &mut R,
+    writer: &mut W,
+    trace: &mut dyn io::Write,
+    pcs: bool,
+    options: &Options,
 ) -> Result<(), Box<dyn error::Error>> {
-    _run(codes, reader, writer, true)
+    let start = Instant::now();
+    let (program, inline) = build(codes, options, start)?;
+    let mut reader = io::Read::chain(inline.as_bytes(), reader);
+    let mut vm = VM::for_program(&program);
+    vm.limit_since(options.limits, start);
+    trace::record(&mut vm, &program, &mut reader, writer, trace, pcs)
 }
 
-fn _run<R: io::Read, W: io::Write>(
+// run codes with the input from a trace of `record`, failing with TraceError::Diverged at the
+// first event differing from it
+pub fn replay<W: io::Write>(
+    codes: &str,
+    trace: &str,
+    writer: &mut W,
+    options: &Options,
+) -> Result<(), Box<dyn error::Error>> {
+    let trace = Trace::parse(trace)?;
+    let start = Instant::now();
+    let (program, _) = build(codes, options, start)?;
+    let mut vm = VM::for_program(&program);
+    vm.limit_since(options.limits, start);
+    trace::replay(&mut vm, &program, &trace, writer)
+}
+
+// the program and the input inlined after "!". preeval is limited as the run time starting at start
+fn build<'a>(
+    codes: &'a str,
+    options: &Options,
+    start: Instant,
+) -> Result<(Program, &'a str), Box<dyn error::Error>> {
+    let (tokens, inline) = tokenize(codes, options.extensions, options)?;
+    let (bytecodes, spans) = bytecode::lower(bytecode::optimize(bytecode::compile(&tokens)?));
+    let mut program = Program {
+        bytecodes,
+        spans,
+        level: OptLevel::FULL,
+        ..Default::default()
+    };
+    // must come last, as the other passes assume the tape is initially zero
+    if let Some(budget) = options.preeval {
+        program = vm::preeval(program, budget, options.limits, start);
+    }
+    Ok((program, inline))
+}
+
+// run codes under the line-oriented debugger (see DEBUGGER_HELP), which reads each command with
+// `commands` and reports to `log`. the program is not optimized so that every source character
+// is an instruction, and "#" is always recognized for breakpoints
+pub fn debug<R: io::Read, W: io::Write>(
     codes: &str,
     reader: &mut R,
     writer: &mut W,
-    jit: bool,
+    commands: &mut dyn FnMut() -> Option<String>,
+    log: &mut dyn io::Write,
+    options: &Options,
 ) -> Result<(), Box<dyn error::Error>> {
-    let tokens = token::tokenize(codes)?;
-    let bytecodes = bytecode::compile(&tokens)?;
-    let program = vm::Program { bytecodes };
-    let mut vm = vm::VM::new();
-    let _ = vm.run(&program, reader, writer, jit)?;
+    let extensions = Extensions {
+        debug: true,
+        ..options.extensions
+    };
+    let (tokens, inline) = tokenize(codes, extensions, options)?;
+    let mut reader = io::Read::chain(inline.as_bytes(), reader);
+    let program = unoptimized(&tokens)?;
+    debugger::Debugger::new(&program, codes).run(&mut reader, writer, commands, log)?;
     Ok(())
 }
+
+// the tokens and the input inlined after "!"
+fn tokenize<'a>(
+    codes: &'a str,
+    extensions: Extensions,
+    options: &Options,
+) -> Result<(Vec<token::Token>, &'a str), Box<dyn error::Error>> {
+    let tokens = match &options.dialect {
+        Some(dialect) => dialect.tokenize(codes, extensions)?,
+        None => token::tokenize_with(codes, extensions)?,
+    };
+    let inline = match tokens.last() {
+        Some(token) if token.kind == token::TokenKind::BANG => &codes[token.span.end..],
+        _ => "",
+    };
+    Ok((tokens, inline))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output_of(program: &Program) -> Vec<u8> {
+        let mut output = vec![];
+        VM::for_program(program)
+            .run(program, &mut io::empty(), &mut output, &Interpreter)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn parse_and_optimize() {
+        let codes = include_str!("../examples/hello_world.bf");
+        let program = parse(codes).unwrap();
+        assert!(!program.bytecodes.contains(&Inst::SETZERO));
+
+        let peephole = program.clone().optimize(OptLevel::PEEPHOLE);
+        let full = program.clone().optimize(OptLevel::FULL);
+        assert!(full.bytecodes.len() <= peephole.bytecodes.len());
+        assert!(peephole.bytecodes.len() < program.bytecodes.len());
+        assert_eq!(program.bytecodes.len(), program.spans.len());
+        assert_eq!(full.bytecodes.len(), full.spans.len());
+        for p in [&peephole, &full, &full.clone().optimize(OptLevel::FULL)] {
+            assert_eq!(output_of(&program), output_of(p));
+        }
+    }
+
+    #[test]
+    fn run_returns_state() {
+        let snapshot = run("++>+++<[->+<]", &mut io::empty(), &mut io::sink()).unwrap();
+        assert_eq!(vm::MEMSIZE / 2, snapshot.mem_ptr);
+        assert_eq!(vec![(vm::MEMSIZE / 2 + 1, vec![5])], snapshot.regions);
+    }
+
+    #[test]
+    fn run_steps_by_engine() {
+        // the same steps with any engine, those of preeval included
+        let codes = "++++++++[>++++++++<-]>[<++>-]<[>+<-]";
+        let steps = run(codes, &mut io::empty(), &mut io::sink()).unwrap().steps;
+        assert!(steps > 0);
+        for name in ENGINES {
+            for preeval in [None, Some(PREEVAL_STEPS)] {
+                let options = Options {
+                    engine: engine(name),
+                    preeval,
+                    ..Default::default()
+                };
+                let snapshot =
+                    run_with_options(codes, &mut io::empty(), &mut io::sink(), &options).unwrap();
+                assert_eq!(steps, snapshot.steps, "{name} {preeval:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn run_inline_input() {
+        // ",[.,]!" echoing the inline input followed by the reader
+        for name in ENGINES {
+            let options = Options {
+                engine: engine(name),
+                extensions: Extensions {
+                    input: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let mut output = vec![];
+            run_with_options(",[.,]!ab!", &mut "cd".as_bytes(), &mut output, &options).unwrap();
+            assert_eq!(b"ab!cd".to_vec(), output);
+        }
+    }
+}
@@ -1,30 +1,411 @@
 use clap::Parser;
-use std::{error, fs, io, process};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{error, fmt, fs, io, path, process};
 
 #[derive(Debug, Parser)]
-#[clap(author, about, version)]
+#[clap(
+    author,
+    about,
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    #[clap(short, long)]
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Same as --engine=jit
+    #[clap(short, long, conflicts_with = "engine")]
     with_jit: bool,
 
+    /// How to execute the program
+    #[clap(long, value_name = "ENGINE", possible_values = &bf_jit::ENGINES)]
+    engine: Option<String>,
+
+    /// Evaluate the program at compile time until it reads input, up to STEPS steps
+    #[clap(long, value_name = "STEPS", require_equals = true)]
+    preeval: Option<Option<usize>>,
+
+    /// Syntax of the program: bf, ook, blub or a TOML (or .json) file mapping each command to its
+    /// words
+    /// [default: by the file extension, or bf]
+    #[clap(long, value_name = "DIALECT")]
+    dialect: Option<String>,
+
+    /// Dump the pointer and the cells around it to stderr at "#"
+    #[clap(long)]
+    debug: bool,
+
+    /// Feed the text after the first "!" in the program to the input, before stdin
+    #[clap(long)]
+    inline_input: bool,
+
+    /// Run the program under an interactive debugger reading commands from stdin
+    #[clap(long, conflicts_with_all = &["with-jit", "engine"])]
+    debugger: bool,
+
+    /// Run without JIT and report the hot loops and instructions to stderr as text or json
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        require_equals = true,
+        possible_values = &["text", "json"],
+        conflicts_with_all = &["debugger", "with-jit", "engine"]
+    )]
+    profile: Option<Option<String>>,
+
+    /// Run without JIT, recording the input and the output to FILE for --replay
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["debugger", "profile", "with-jit", "engine"]
+    )]
+    trace: Option<String>,
+
+    /// Record every executed instruction in the trace too
+    #[clap(long, requires = "trace")]
+    trace_pcs: bool,
+
+    /// Run with the input from a trace of --trace, and check that the execution and the output
+    /// are the same
+    #[clap(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = &["debugger", "profile", "trace", "with-jit", "engine"]
+    )]
+    replay: Option<String>,
+
+    /// Print the program translated to FORMAT instead of running it
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        possible_values = &["asm", "c", "wat"],
+        conflicts_with_all = &["debugger", "profile", "trace", "replay"]
+    )]
+    emit: Option<String>,
+
+    /// Stop after about STEPS instructions (exit code 4)
+    #[clap(long, value_name = "STEPS")]
+    max_steps: Option<u64>,
+
+    /// Stop after SECS seconds (exit code 5)
+    #[clap(long, value_name = "SECS")]
+    timeout: Option<f64>,
+
+    /// Stop at the first output beyond BYTES bytes (exit code 6)
+    #[clap(long, value_name = "BYTES")]
+    max_output: Option<usize>,
+
+    #[clap(required = true)]
+    filename: Option<String>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Time the built-in benchmarks under each engine and optimization level
+    Bench(Bench),
+    /// Compile the program into a standalone x86-64 Linux executable
+    Compile(Compile),
+}
+
+#[derive(Debug, clap::Args)]
+struct Compile {
+    /// Write the executable to FILE [default: the program without its extension]
+    #[clap(short, long, value_name = "FILE")]
+    output: Option<String>,
+
+    /// Evaluate the program at compile time until it reads input, up to STEPS steps
+    #[clap(long, value_name = "STEPS", require_equals = true)]
+    preeval: Option<Option<usize>>,
+
+    /// Syntax of the program, as in the run without a subcommand
+    #[clap(long, value_name = "DIALECT")]
+    dialect: Option<String>,
+
     filename: String,
 }
 
-fn _main() -> Result<(), Box<dyn error::Error + 'static>> {
+#[derive(Debug, clap::Args)]
+struct Bench {
+    /// Only these benchmarks: mandelbrot, hanoi, factor or long_loop [default: all]
+    #[clap(value_name = "BENCHMARK")]
+    benchmarks: Vec<String>,
+
+    /// Only these engines [default: all]
+    #[clap(
+        long,
+        value_name = "ENGINE",
+        possible_values = &bf_jit::ENGINES,
+        multiple_occurrences = true
+    )]
+    engine: Vec<String>,
+
+    /// Only these optimization levels [default: all]
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        possible_values = &["none", "peephole", "full"],
+        multiple_occurrences = true
+    )]
+    level: Vec<String>,
+
+    /// Keep the best of RUNS runs of each
+    #[clap(long, value_name = "RUNS", default_value_t = 1)]
+    runs: usize,
+
+    /// Print the results as JSON at the end, e.g. to compare them between commits
+    #[clap(long)]
+    json: bool,
+}
+
+fn _main() -> Result<process::ExitCode, Box<dyn error::Error + 'static>> {
     let args = Args::parse();
-    let input = fs::read_to_string(args.filename)?;
+    match &args.command {
+        Some(Command::Bench(bench)) => return run_bench(bench),
+        Some(Command::Compile(compile)) => return run_compile(compile),
+        None => (),
+    }
+    let filename = args.filename.as_deref().unwrap_or_default();
+    let timeout = match args.timeout {
+        Some(secs) if !(secs.is_finite() && secs >= 0.0) => {
+            return Err(format!("invalid timeout: {secs}").into())
+        }
+        timeout => timeout.map(Duration::from_secs_f64),
+    };
+    let input = fs::read_to_string(filename)?;
 
-    if args.with_jit {
-        bf_jit::run_with_jit(&input, &mut io::stdin(), &mut io::stdout())?;
+    let dialect = dialect(args.dialect.as_deref(), filename)?;
+
+    let options = bf_jit::Options {
+        engine: match (&args.engine, args.with_jit) {
+            (Some(name), _) => bf_jit::engine(name),
+            (None, true) => Some(Arc::new(bf_jit::Jit)),
+            (None, false) => None,
+        },
+        preeval: args
+            .preeval
+            .map(|steps| steps.unwrap_or(bf_jit::PREEVAL_STEPS)),
+        dialect: Some(dialect),
+        extensions: bf_jit::Extensions {
+            debug: args.debug,
+            input: args.inline_input,
+        },
+        limits: bf_jit::Limits {
+            max_steps: args.max_steps,
+            timeout,
+            max_output: args.max_output,
+        },
+    };
+    let res = if let Some(format) = &args.emit {
+        match format.as_str() {
+            "asm" => bf_jit::compile_asm(&input, &options).map(|asm| print!("{asm}")),
+            "c" => bf_jit::compile_c(&input, &options).map(|c| print!("{c}")),
+            "wat" => bf_jit::compile_wat(&input, &options).map(|wat| print!("{wat}")),
+            _ => unreachable!("not in the possible values"),
+        }
+    } else if args.debugger {
+        eprintln!("{}", bf_jit::DEBUGGER_HELP);
+        // the commands and the input of the program share stdin
+        let mut commands = || {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) | Err(_) => None,
+                Ok(_) => Some(line),
+            }
+        };
+        bf_jit::debug(
+            &input,
+            &mut io::stdin(),
+            &mut io::stdout(),
+            &mut commands,
+            &mut io::stderr(),
+            &options,
+        )
+    } else if let Some(path) = &args.trace {
+        let mut trace = io::BufWriter::new(fs::File::create(path)?);
+        bf_jit::record(
+            &input,
+            &mut io::stdin(),
+            &mut io::stdout(),
+            &mut trace,
+            args.trace_pcs,
+            &options,
+        )
+    } else if let Some(path) = &args.replay {
+        let trace = fs::read_to_string(path)?;
+        bf_jit::replay(&input, &trace, &mut io::stdout(), &options)
+    } else if let Some(format) = &args.profile {
+        bf_jit::profile(&input, &mut io::stdin(), &mut io::stdout(), &options).map(|report| {
+            match format.as_deref() {
+                Some("json") => eprint!("{}", report.to_json()),
+                _ => eprint!("{report}"),
+            }
+        })
     } else {
-        bf_jit::run(&input, &mut io::stdin(), &mut io::stdout())?;
+        bf_jit::run_with_options(&input, &mut io::stdin(), &mut io::stdout(), &options).map(|_| ())
+    };
+    if let Err(e) = res {
+        let code = e.downcast_ref().map_or(1, exit_code);
+        eprintln!("Error: {}", render(e, filename, &input));
+        return Ok(process::ExitCode::from(code));
+    }
+    Ok(process::ExitCode::SUCCESS)
+}
+
+// the dialect named by --dialect, or by the extension of filename
+fn dialect(
+    name: Option<&str>,
+    filename: &str,
+) -> Result<Arc<dyn bf_jit::Dialect>, Box<dyn error::Error>> {
+    Ok(match name {
+        Some(name) => match bf_jit::dialect(name) {
+            Some(dialect) => dialect,
+            None => {
+                let src = fs::read_to_string(name).map_err(|e| format!("{name}: {e}"))?;
+                let words = match path::Path::new(name).extension() {
+                    Some(ext) if ext == "json" => bf_jit::Words::from_json(&src),
+                    _ => bf_jit::Words::from_toml(&src),
+                };
+                Arc::new(words.map_err(|e| format!("{name}:{e}"))?)
+            }
+        },
+        None => path::Path::new(filename)
+            .extension()
+            .and_then(|ext| bf_jit::dialect(ext.to_str()?))
+            .unwrap_or_else(|| Arc::new(bf_jit::Brainfuck)),
+    })
+}
+
+fn run_compile(compile: &Compile) -> Result<process::ExitCode, Box<dyn error::Error>> {
+    let input = fs::read_to_string(&compile.filename)?;
+    let options = bf_jit::Options {
+        preeval: compile
+            .preeval
+            .map(|steps| steps.unwrap_or(bf_jit::PREEVAL_STEPS)),
+        dialect: Some(dialect(compile.dialect.as_deref(), &compile.filename)?),
+        ..Default::default()
+    };
+    let executable = match bf_jit::compile_elf(&input, &options) {
+        Ok(executable) => executable,
+        Err(e) => {
+            eprintln!("Error: {}", render(e, &compile.filename, &input));
+            return Ok(process::ExitCode::FAILURE);
+        }
+    };
+    let output = match &compile.output {
+        Some(output) => path::PathBuf::from(output),
+        None => path::Path::new(&compile.filename).with_extension(""),
+    };
+    if output == path::Path::new(&compile.filename) {
+        return Err(format!("{}: would overwrite the program", output.display()).into());
+    }
+    fs::write(&output, executable)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&output, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(process::ExitCode::SUCCESS)
+}
+
+fn run_bench(bench: &Bench) -> Result<process::ExitCode, Box<dyn error::Error>> {
+    let benchmarks = match bench.benchmarks.as_slice() {
+        [] => bf_jit::BENCHMARKS.to_vec(),
+        names => names
+            .iter()
+            .map(|name| bf_jit::benchmark(name).ok_or(format!("unknown benchmark: {name}")))
+            .collect::<Result<_, _>>()?,
+    };
+    let engines: Vec<_> = bf_jit::ENGINES
+        .into_iter()
+        .filter(|engine| bench.engine.is_empty() || bench.engine.iter().any(|e| e == engine))
+        .collect();
+    let levels = match bench.level.as_slice() {
+        [] => bf_jit::OptLevel::ALL.to_vec(),
+        names => names
+            .iter()
+            .map(|name| bf_jit::OptLevel::by_name(name).ok_or(format!("unknown level: {name}")))
+            .collect::<Result<_, _>>()?,
+    };
+
+    // the rows go out as they are measured, as a whole run takes minutes
+    let mut results = bf_jit::BenchResults::default();
+    if !bench.json {
+        println!("{}", bf_jit::Measurement::HEADER);
+    }
+    for benchmark in benchmarks.iter() {
+        for &engine in engines.iter() {
+            for &level in levels.iter() {
+                let measurement = bf_jit::measure(benchmark, engine, level, bench.runs)?;
+                if !bench.json {
+                    println!("{measurement}");
+                }
+                results.measurements.push(measurement);
+            }
+        }
+    }
+    if bench.json {
+        print!("{}", results.to_json());
+    }
+    Ok(process::ExitCode::SUCCESS)
+}
+
+// prefix errors pointing into the source with the filename and show the offending code
+fn render(e: Box<dyn error::Error>, filename: &str, input: &str) -> Box<dyn error::Error> {
+    let annotate = |e: &dyn fmt::Display, span| {
+        format!(
+            "{filename}:{e}\n{}",
+            bf_jit::snippet(input, span).trim_end()
+        )
+    };
+    if let Some(e) = e.downcast_ref::<bf_jit::CompileError>() {
+        let errors = match e {
+            bf_jit::CompileError::Multiple(errors) => errors.iter().collect(),
+            e => vec![e],
+        };
+        return errors
+            .into_iter()
+            .filter_map(|e| Some(annotate(e, e.span()?)))
+            .collect::<Vec<_>>()
+            .join("\nError: ")
+            .into();
+    }
+    if let Some(e @ bf_jit::TraceError::Diverged(_)) = e.downcast_ref() {
+        return match e.span() {
+            Some(span) => annotate(e, span).into(),
+            None => e.to_string().into(),
+        };
+    }
+    match e.downcast_ref::<bf_jit::RuntimeError>() {
+        Some(
+            re @ bf_jit::RuntimeError::MemoryOutofRange {
+                span: Some(span),
+                tape,
+                ..
+            },
+        ) => {
+            let gutter = " ".repeat(span.line.to_string().len());
+            format!("{}\n{gutter} = note: {tape}", annotate(re, *span)).into()
+        }
+        Some(re) if re.span().is_some() => annotate(re, re.span().unwrap()).into(),
+        _ => e,
+    }
+}
+
+// 1 for the other errors
+fn exit_code(e: &bf_jit::RuntimeError) -> u8 {
+    match e {
+        bf_jit::RuntimeError::MemoryOutofRange { .. } => 3,
+        bf_jit::RuntimeError::StepLimit { .. } => 4,
+        bf_jit::RuntimeError::TimeLimit { .. } => 5,
+        bf_jit::RuntimeError::OutputLimit { .. } => 6,
     }
-    Ok(())
 }
 
 fn main() -> process::ExitCode {
     match _main() {
-        Ok(_) => process::ExitCode::SUCCESS,
+        Ok(code) => code,
         Err(e) => {
             eprintln!("Error: {e}");
             process::ExitCode::FAILURE
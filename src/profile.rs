@@ -0,0 +1,369 @@
+use crate::bytecode::{self, Block, Inst, Node};
+use crate::token::Span;
+use crate::vm::{Program, RuntimeError, Step, VM};
+use std::fmt::{self, Write};
+use std::io;
+
+// the rows of each table in the text report
+const TOP: usize = 20;
+
+// the optimized instructions reported with the source commands they stand for
+const OPTIMIZATIONS: [&str; 3] = ["MULINTO", "FINDZERO", "SETZERO"];
+
+// executions of each instruction of a program
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub counts: Vec<u64>, // by pc
+}
+
+// run program to the end on vm without the JIT, counting the instructions
+pub fn profile<R: io::Read, W: io::Write>(
+    vm: &mut VM,
+    program: &Program,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<Profile, RuntimeError> {
+    let mut counts = vec![0; program.bytecodes.len()];
+    while vm.pc() < program.bytecodes.len() {
+        counts[vm.pc()] += 1;
+        if let Step::NeedInput = vm.step(program, reader, writer)? {
+            // no input for now is taken as the end of it, as in VM::run
+            vm.eof();
+        }
+    }
+    Ok(Profile { counts })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstCost {
+    pub pc: usize,
+    pub inst: Inst,
+    pub span: Option<Span>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoopCost {
+    pub open: usize,  // pc of the JZ
+    pub close: usize, // pc of the JNZ, or of the last instruction of an if
+    pub span: Option<Span>,
+    pub entries: u64,
+    pub iterations: u64,
+    pub steps: u64, // instructions executed in the loop, including the nested loops
+    // a loop found to run at most once, lowered to a JZ without JNZ
+    pub once: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizationCost {
+    pub name: &'static str,
+    pub sites: usize,
+    pub executions: u64,
+    pub commands: u64, // source commands the executions stood for, without repeating the loops
+}
+
+// a profile mapped back to the program, each table sorted by cost
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub total: u64,
+    pub insts: Vec<InstCost>,
+    pub loops: Vec<LoopCost>,
+    pub optimizations: Vec<OptimizationCost>,
+}
+
+impl Report {
+    // `commands` counts the source commands in a span
+    pub fn new(program: &Program, profile: &Profile, commands: &dyn Fn(Span) -> usize) -> Self {
+        let counts = &profile.counts;
+        let span = |pc: usize| program.spans.get(pc).copied();
+
+        let mut insts: Vec<_> = program
+            .bytecodes
+            .iter()
+            .enumerate()
+            .filter(|&(pc, _)| counts[pc] > 0)
+            .map(|(pc, inst)| InstCost {
+                pc,
+                inst: inst.clone(),
+                span: span(pc),
+                count: counts[pc],
+            })
+            .collect();
+        insts.sort_by(|a, b| b.count.cmp(&a.count).then(a.pc.cmp(&b.pc)));
+
+        let mut found = vec![];
+        find_loops(
+            &bytecode::raise(&program.bytecodes, &program.spans),
+            0,
+            &mut found,
+        );
+        let mut loops: Vec<_> = found
+            .into_iter()
+            .filter(|&(open, _, _)| counts[open] > 0)
+            .map(|(open, exit, once)| LoopCost {
+                open,
+                close: (exit - 1).max(open),
+                span: span(open),
+                entries: counts[open],
+                // the body of an if is run each time its first instruction is
+                iterations: if !once {
+                    counts[exit - 1]
+                } else if exit > open + 1 {
+                    counts[open + 1]
+                } else {
+                    0
+                },
+                steps: counts[open..exit].iter().sum(),
+                once,
+            })
+            .collect();
+        loops.sort_by(|a, b| b.steps.cmp(&a.steps).then(a.open.cmp(&b.open)));
+
+        let optimizations = OPTIMIZATIONS
+            .iter()
+            .map(|&name| {
+                let sites: Vec<_> = (0..program.bytecodes.len())
+                    .filter(|&pc| name_of(&program.bytecodes[pc]) == name)
+                    .collect();
+                OptimizationCost {
+                    name,
+                    sites: sites.len(),
+                    executions: sites.iter().map(|&pc| counts[pc]).sum(),
+                    commands: sites
+                        .iter()
+                        .map(|&pc| counts[pc] * span(pc).map_or(0, commands) as u64)
+                        .sum(),
+                }
+            })
+            .collect();
+
+        Self {
+            total: counts.iter().sum(),
+            insts,
+            loops,
+            optimizations,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let location = |span: Option<Span>| match span {
+            Some(Span { line, col, .. }) => format!("\"line\": {line}, \"col\": {col}"),
+            None => "\"line\": null, \"col\": null".to_string(),
+        };
+        let mut json = format!("{{\n  \"total\": {},\n  \"instructions\": [", self.total);
+        for (i, cost) in self.insts.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{\"pc\": {}, \"inst\": \"{:?}\", {}, \"count\": {}}}",
+                if i > 0 { "," } else { "" },
+                cost.pc,
+                cost.inst,
+                location(cost.span),
+                cost.count
+            );
+        }
+        json.push_str("\n  ],\n  \"loops\": [");
+        for (i, cost) in self.loops.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{\"open\": {}, \"close\": {}, {}, \"entries\": {}, \"iterations\": {}, \"steps\": {}, \"if\": {}}}",
+                if i > 0 { "," } else { "" },
+                cost.open,
+                cost.close,
+                location(cost.span),
+                cost.entries,
+                cost.iterations,
+                cost.steps,
+                cost.once
+            );
+        }
+        json.push_str("\n  ],\n  \"optimizations\": [");
+        for (i, cost) in self.optimizations.iter().enumerate() {
+            let _ = write!(
+                json,
+                "{}\n    {{\"name\": \"{}\", \"sites\": {}, \"executions\": {}, \"commands\": {}}}",
+                if i > 0 { "," } else { "" },
+                cost.name,
+                cost.sites,
+                cost.executions,
+                cost.commands
+            );
+        }
+        json.push_str("\n  ]\n}\n");
+        json
+    }
+}
+
+// the top of each table
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let location = |span: Option<Span>| match span {
+            Some(Span { line, col, .. }) => format!("{line}:{col}"),
+            None => "-".to_string(),
+        };
+        let percent = |n: u64| n as f64 * 100.0 / self.total.max(1) as f64;
+
+        writeln!(f, "{} instructions executed", self.total)?;
+        writeln!(f, "\nhot loops:")?;
+        writeln!(
+            f,
+            "{:>14} {:>7} {:>12} {:>10}  location",
+            "steps", "", "iterations", "entries"
+        )?;
+        for cost in self.loops.iter().take(TOP) {
+            writeln!(
+                f,
+                "{:>14} {:>6.2}% {:>12} {:>10}  {}{}",
+                cost.steps,
+                percent(cost.steps),
+                cost.iterations,
+                cost.entries,
+                location(cost.span),
+                if cost.once { " (if)" } else { "" }
+            )?;
+        }
+        writeln!(f, "\nhot instructions:")?;
+        writeln!(
+            f,
+            "{:>14} {:>7} {:>8}  location  instruction",
+            "count", "", "pc"
+        )?;
+        for cost in self.insts.iter().take(TOP) {
+            writeln!(
+                f,
+                "{:>14} {:>6.2}% {:>8}  {:<8}  {:?}",
+                cost.count,
+                percent(cost.count),
+                cost.pc,
+                location(cost.span),
+                cost.inst
+            )?;
+        }
+        writeln!(f, "\noptimizations:")?;
+        writeln!(
+            f,
+            "{:>14} {:>7} {:>14}  commands replaced",
+            "", "sites", "executions"
+        )?;
+        for cost in self.optimizations.iter() {
+            writeln!(
+                f,
+                "{:>14} {:>7} {:>14}  {}",
+                cost.name, cost.sites, cost.executions, cost.commands
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// the loops and the ifs in block lowered from pc on, as (pc of the JZ, pc after the loop, whether
+// an if), returning the pc after block
+fn find_loops(block: &Block, mut pc: usize, found: &mut Vec<(usize, usize, bool)>) -> usize {
+    for node in block {
+        pc = match node {
+            Node::Op(..) => pc + 1,
+            Node::Loop { body, .. } => {
+                let exit = find_loops(body, pc + 1, found) + 1;
+                found.push((pc, exit, false));
+                exit
+            }
+            Node::If { body, .. } => {
+                let exit = find_loops(body, pc + 1, found);
+                found.push((pc, exit, true));
+                exit
+            }
+        };
+    }
+    pc
+}
+
+fn name_of(inst: &Inst) -> &'static str {
+    match inst {
+        Inst::MULINTO(..) => "MULINTO",
+        Inst::FINDZERO(_) => "FINDZERO",
+        Inst::SETZERO => "SETZERO",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::{compile, lower, optimize};
+    use crate::token::tokenize;
+
+    fn report(codes: &str) -> Report {
+        let (bytecodes, spans) = lower(optimize(compile(&tokenize(codes).unwrap()).unwrap()));
+        let program = Program {
+            bytecodes,
+            spans,
+            ..Default::default()
+        };
+        let profile = profile(
+            &mut VM::for_program(&program),
+            &program,
+            &mut io::empty(),
+            &mut vec![],
+        )
+        .unwrap();
+        Report::new(&program, &profile, &|span| {
+            codes[span.start..span.end]
+                .chars()
+                .filter(|c| "<>+-.,[]".contains(*c))
+                .count()
+        })
+    }
+
+    #[test]
+    fn report_loops() {
+        // the outer loop runs 3 times, the inner one 2 times each
+        let report = report("+++[>++[>+.<-]<-]");
+        assert_eq!(
+            vec![(6, 3, 39), (3, 1, 55)],
+            report
+                .loops
+                .iter()
+                .map(|cost| (cost.iterations, cost.entries, cost.steps))
+                .rev()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(report.loops[0].steps + 1, report.total);
+    }
+
+    #[test]
+    fn report_ifs() {
+        // the inner loop always ends at zero, so it is an if, entered and run 3 times
+        let report = report("+++[>+[.[-]]<-]");
+        assert_eq!(
+            vec![(false, 3, 1), (true, 3, 3)],
+            report
+                .loops
+                .iter()
+                .map(|cost| (cost.once, cost.iterations, cost.entries))
+                .collect::<Vec<_>>()
+        );
+        assert!(report.to_string().contains(" (if)\n"));
+    }
+
+    #[test]
+    fn report_optimizations() {
+        // the "+++" overwritten by "[-]" is folded into SETZERO
+        let report = report("+++[-]>++[-<+>]");
+        let costs: Vec<_> = report
+            .optimizations
+            .iter()
+            .map(|cost| (cost.name, cost.sites, cost.executions, cost.commands))
+            .collect();
+        assert_eq!(
+            vec![
+                ("MULINTO", 1, 1, 6),
+                ("FINDZERO", 0, 0, 0),
+                ("SETZERO", 1, 1, 6)
+            ],
+            costs
+        );
+        assert!(report
+            .to_json()
+            .contains("{\"name\": \"SETZERO\", \"sites\": 1"));
+    }
+}
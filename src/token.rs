@@ -1,5 +1,16 @@
-#[derive(PartialEq, Debug)]
-pub enum Token {
+mod dialect;
+
+pub use self::dialect::{by_name, Brainfuck, Dialect, DialectError, Words};
+
+// opt-in commands beyond the eight
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Extensions {
+    pub debug: bool, // "#" dumps the tape
+    pub input: bool, // "!" ends the program, and the rest is fed to the input
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TokenKind {
     LT,
     GT,
     PLUS,
@@ -8,21 +19,77 @@ pub enum Token {
     COMMA,
     LSQB,
     RSQB,
+    HASH,
+    BANG, // always the last token
 }
 
+// [start, end) in bytes, line and col (1-origin, in chars) of start
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    // from the start of self to the end of other
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+#[cfg(test)]
 pub fn tokenize(codes: &str) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
+    tokenize_with(codes, Extensions::default())
+}
+
+pub fn tokenize_with(
+    codes: &str,
+    extensions: Extensions,
+) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
     let mut tokens = vec![];
-    for c in codes.chars() {
-        match c {
-            '<' => tokens.push(Token::LT),
-            '>' => tokens.push(Token::GT),
-            '+' => tokens.push(Token::PLUS),
-            '-' => tokens.push(Token::MINUS),
-            '.' => tokens.push(Token::DOT),
-            ',' => tokens.push(Token::COMMA),
-            '[' => tokens.push(Token::LSQB),
-            ']' => tokens.push(Token::RSQB),
-            _ => (),
+    let (mut line, mut col) = (1, 1);
+    for (start, c) in codes.char_indices() {
+        let kind = match c {
+            '<' => Some(TokenKind::LT),
+            '>' => Some(TokenKind::GT),
+            '+' => Some(TokenKind::PLUS),
+            '-' => Some(TokenKind::MINUS),
+            '.' => Some(TokenKind::DOT),
+            ',' => Some(TokenKind::COMMA),
+            '[' => Some(TokenKind::LSQB),
+            ']' => Some(TokenKind::RSQB),
+            '#' if extensions.debug => Some(TokenKind::HASH),
+            '!' if extensions.input => Some(TokenKind::BANG),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let span = Span {
+                start,
+                end: start + 1,
+                line,
+                col,
+            };
+            tokens.push(Token { kind, span });
+            if kind == TokenKind::BANG {
+                break;
+            }
+        }
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
         }
     }
     Ok(tokens)
@@ -30,13 +97,65 @@ pub fn tokenize(codes: &str) -> Result<Vec<Token>, Box<dyn std::error::Error>> {
 
 #[cfg(test)]
 mod tests {
-    use super::Token::*;
+    use super::TokenKind::*;
     use super::*;
     #[test]
     fn tokenize_test() {
         let codes = " test \n>[-].,+<//;; 0;;\n";
         assert_eq!(
             vec![GT, LSQB, MINUS, RSQB, DOT, COMMA, PLUS, LT],
+            tokenize(codes)
+                .unwrap()
+                .iter()
+                .map(|token| token.kind)
+                .collect::<Vec<_>>()
+        )
+    }
+
+    #[test]
+    fn tokenize_extensions() {
+        let codes = "+#!#,";
+        let kinds = |extensions| {
+            tokenize_with(codes, extensions)
+                .unwrap()
+                .iter()
+                .map(|token| token.kind)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![PLUS, COMMA], kinds(Extensions::default()));
+        assert_eq!(
+            vec![PLUS, HASH, BANG],
+            kinds(Extensions {
+                debug: true,
+                input: true
+            })
+        );
+    }
+
+    #[test]
+    fn tokenize_span() {
+        let codes = "é+\n ,";
+        assert_eq!(
+            vec![
+                Token {
+                    kind: PLUS,
+                    span: Span {
+                        start: 2,
+                        end: 3,
+                        line: 1,
+                        col: 2
+                    }
+                },
+                Token {
+                    kind: COMMA,
+                    span: Span {
+                        start: 5,
+                        end: 6,
+                        line: 2,
+                        col: 2
+                    }
+                }
+            ],
             tokenize(codes).unwrap()
         )
     }
@@ -0,0 +1,381 @@
+use super::{tokenize_with, Extensions, Span, Token, TokenKind};
+use std::sync::Arc;
+use std::{error, fmt};
+mod table;
+
+// a syntax for the eight commands
+pub trait Dialect: fmt::Debug {
+    fn tokenize(
+        &self,
+        codes: &str,
+        extensions: Extensions,
+    ) -> Result<Vec<Token>, Box<dyn error::Error>>;
+}
+
+// the one character per command syntax
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Brainfuck;
+
+impl Dialect for Brainfuck {
+    fn tokenize(
+        &self,
+        codes: &str,
+        extensions: Extensions,
+    ) -> Result<Vec<Token>, Box<dyn error::Error>> {
+        tokenize_with(codes, extensions)
+    }
+}
+
+// commands spelled as sequences of whitespace separated words, other words are comments.
+// the extensions are spelled as the words "#" and "!"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Words {
+    commands: Vec<(Vec<String>, TokenKind)>,
+}
+
+const COMMANDS: [(char, TokenKind); 8] = [
+    ('<', TokenKind::LT),
+    ('>', TokenKind::GT),
+    ('+', TokenKind::PLUS),
+    ('-', TokenKind::MINUS),
+    ('.', TokenKind::DOT),
+    (',', TokenKind::COMMA),
+    ('[', TokenKind::LSQB),
+    (']', TokenKind::RSQB),
+];
+
+impl Words {
+    // spellings in the order of "<>+-.,[]"
+    fn new(spellings: &[&str]) -> Self {
+        let mut commands: Vec<(Vec<String>, TokenKind)> = spellings
+            .iter()
+            .zip(COMMANDS)
+            .map(|(s, (_, kind))| (s.split_whitespace().map(String::from).collect(), kind))
+            .collect();
+        // the longest spelling matches first, e.g. "go right" before "go"
+        commands.sort_by_key(|(spelling, _)| std::cmp::Reverse(spelling.len()));
+        Self { commands }
+    }
+
+    // https://esolangs.org/wiki/Ook!
+    pub fn ook() -> Self {
+        Self::pairs("Ook")
+    }
+
+    // https://esolangs.org/wiki/Blub
+    pub fn blub() -> Self {
+        Self::pairs("Blub")
+    }
+
+    // the Ook! family, commands are pairs of word followed by one of ".?!"
+    fn pairs(word: &str) -> Self {
+        let spell = |a, b| format!("{word}{a} {word}{b}");
+        Self::new(&[
+            &spell('?', '.'),
+            &spell('.', '?'),
+            &spell('.', '.'),
+            &spell('!', '!'),
+            &spell('!', '.'),
+            &spell('.', '!'),
+            &spell('!', '?'),
+            &spell('?', '!'),
+        ])
+    }
+
+    // a TOML document of each command to its spelling, e.g.
+    //   "+" = "Ook. Ook."
+    pub fn from_toml(src: &str) -> Result<Self, DialectError> {
+        Self::from_pairs(table::toml(src)?)
+    }
+
+    // the same as a JSON object, e.g.
+    //   {"+": "Ook. Ook."}
+    pub fn from_json(src: &str) -> Result<Self, DialectError> {
+        Self::from_pairs(table::json(src)?)
+    }
+
+    fn from_pairs(pairs: table::Pairs) -> Result<Self, DialectError> {
+        let mut spellings: [Option<String>; 8] = Default::default();
+        for (key, value, line) in pairs {
+            let command = COMMANDS
+                .iter()
+                .position(|&(c, _)| key.len() == 1 && key.starts_with(c))
+                .ok_or_else(|| DialectError::UnknownCommand(key.clone()))?;
+            if value.split_whitespace().next().is_none() {
+                return Err(DialectError::Syntax(
+                    line,
+                    "expected some words".to_string(),
+                ));
+            }
+            if spellings[command].replace(value).is_some() {
+                return Err(DialectError::Syntax(
+                    line,
+                    format!("duplicate key \"{key}\""),
+                ));
+            }
+        }
+
+        if let Some(i) = spellings.iter().position(Option::is_none) {
+            return Err(DialectError::MissingCommand(COMMANDS[i].0));
+        }
+        let spellings: Vec<&str> = spellings.iter().map(|s| s.as_deref().unwrap()).collect();
+        Ok(Self::new(&spellings))
+    }
+}
+
+impl Dialect for Words {
+    fn tokenize(
+        &self,
+        codes: &str,
+        extensions: Extensions,
+    ) -> Result<Vec<Token>, Box<dyn error::Error>> {
+        let words = split_words(codes);
+        let mut tokens = vec![];
+        let mut i = 0;
+        while i < words.len() {
+            let (word, span) = words[i];
+            let kind = match word {
+                "#" if extensions.debug => Some(TokenKind::HASH),
+                "!" if extensions.input => Some(TokenKind::BANG),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                tokens.push(Token { kind, span });
+                if kind == TokenKind::BANG {
+                    break;
+                }
+                i += 1;
+                continue;
+            }
+
+            let found = self.commands.iter().find(|(spelling, _)| {
+                words[i..]
+                    .iter()
+                    .map(|&(w, _)| w)
+                    .take(spelling.len())
+                    .eq(spelling.iter().map(String::as_str))
+            });
+            match found {
+                Some((spelling, kind)) => {
+                    let span = words[i].1.to(words[i + spelling.len() - 1].1);
+                    tokens.push(Token { kind: *kind, span });
+                    i += spelling.len();
+                }
+                None => i += 1,
+            }
+        }
+        Ok(tokens)
+    }
+}
+
+// whitespace separated words with their spans
+fn split_words(codes: &str) -> Vec<(&str, Span)> {
+    let mut words = vec![];
+    let mut current: Option<Span> = None;
+    let (mut line, mut col) = (1, 1);
+    for (i, c) in codes.char_indices() {
+        match (c.is_whitespace(), current.as_mut()) {
+            (true, Some(span)) => {
+                words.push((&codes[span.start..i], *span));
+                current = None;
+            }
+            (false, Some(span)) => span.end = i + c.len_utf8(),
+            (false, None) => {
+                current = Some(Span {
+                    start: i,
+                    end: i + c.len_utf8(),
+                    line,
+                    col,
+                })
+            }
+            (true, None) => (),
+        }
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    if let Some(span) = current {
+        words.push((&codes[span.start..], span));
+    }
+    words
+}
+
+// a built-in dialect by its name, which is also the file extension
+pub fn by_name(name: &str) -> Option<Arc<dyn Dialect>> {
+    match name {
+        "bf" | "b" | "brainfuck" => Some(Arc::new(Brainfuck)),
+        "ook" => Some(Arc::new(Words::ook())),
+        "blub" => Some(Arc::new(Words::blub())),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DialectError {
+    Syntax(usize, String), // line and what was expected there
+    UnknownCommand(String),
+    MissingCommand(char),
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DialectError::*;
+        match self {
+            Syntax(line, expected) => write!(f, "{line}: {expected}"),
+            UnknownCommand(key) => write!(f, "unknown command \"{key}\""),
+            MissingCommand(c) => write!(f, "no spelling for '{c}'"),
+        }
+    }
+}
+
+impl error::Error for DialectError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::TokenKind::*;
+
+    fn kinds(dialect: &dyn Dialect, codes: &str) -> Vec<TokenKind> {
+        dialect
+            .tokenize(codes, Extensions::default())
+            .unwrap()
+            .iter()
+            .map(|token| token.kind)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_ook() {
+        let codes =
+            "Ook. Ook. Ook! Ook?\nOok? Ook. Ook! Ook! Ook? Ook!\tOok! Ook. Ook. Ook! Ook. Ook?";
+        assert_eq!(
+            vec![PLUS, LSQB, LT, MINUS, RSQB, DOT, COMMA, GT],
+            kinds(&Words::ook(), codes)
+        );
+
+        let tokens = Words::ook().tokenize(codes, Extensions::default()).unwrap();
+        assert_eq!(
+            Span {
+                start: 20,
+                end: 29,
+                line: 2,
+                col: 1
+            },
+            tokens[2].span
+        );
+    }
+
+    #[test]
+    fn tokenize_longest() {
+        let toml = r#"
+            "<" = "go"
+            ">" = "go right"
+            "+" = "inc"
+            "-" = "dec"
+            "." = "put"
+            "," = "get"
+            "[" = "while"
+            "]" = "end"
+        "#;
+        let dialect = Words::from_toml(toml).unwrap();
+        assert_eq!(
+            vec![GT, LT, PLUS, LT],
+            kinds(&dialect, "go right go inc go")
+        );
+    }
+
+    #[test]
+    fn tokenize_ook_comments() {
+        assert_eq!(
+            vec![PLUS, DOT],
+            kinds(&Words::ook(), "an Ook. Ook. orangutan\nOok! Ook.")
+        );
+    }
+
+    #[test]
+    fn tokenize_ook_extensions() {
+        let extensions = Extensions {
+            debug: true,
+            input: true,
+        };
+        let tokens = Words::ook()
+            .tokenize("Ook. Ook. # Ook! Ook. ! Ook. Ook.", extensions)
+            .unwrap();
+        assert_eq!(
+            vec![PLUS, HASH, DOT, BANG],
+            tokens.iter().map(|token| token.kind).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn tokenize_blub() {
+        assert_eq!(
+            vec![PLUS, LSQB, MINUS, RSQB],
+            kinds(
+                &Words::blub(),
+                "Blub. Blub. Blub! Blub? Blub! Blub! Blub? Blub!"
+            )
+        );
+    }
+
+    #[test]
+    fn tokenize_brainfuck() {
+        assert_eq!(vec![PLUS, LSQB, RSQB], kinds(&Brainfuck, "+ [ ]"));
+    }
+
+    #[test]
+    fn tokenize_from_toml() {
+        let toml = r#"
+            # words
+            "<" = "left"
+            ">" = "right"
+            "+" = "inc"
+            "-" = "dec"
+            "." = "put char"
+            "," = "get"
+            "[" = "while"
+            "]" = "end"
+        "#;
+        let dialect = Words::from_toml(toml).unwrap();
+        assert_eq!(
+            vec![GT, PLUS, LSQB, DOT, MINUS, RSQB],
+            kinds(&dialect, "right inc while put char dec end put")
+        );
+    }
+
+    #[test]
+    fn tokenize_from_json() {
+        let json = r#"{
+            "<": "left", ">": "right", "+": "inc", "-": "dec",
+            ".": "put\tchar", ",": "get", "[": "while", "]": "end"
+        }"#;
+        let dialect = Words::from_json(json).unwrap();
+        assert_eq!(
+            vec![GT, PLUS, LSQB, DOT, MINUS, RSQB],
+            kinds(&dialect, "right inc while put char dec end put")
+        );
+    }
+
+    #[test]
+    fn from_toml_error() {
+        assert_eq!(
+            Err(DialectError::Syntax(1, "expected a string".to_string())),
+            Words::from_toml("\"+\" = inc")
+        );
+        assert_eq!(
+            Err(DialectError::Syntax(2, "duplicate key \"+\"".to_string())),
+            Words::from_toml("\"+\" = \"inc\"\n'+' = \"add\"")
+        );
+        assert_eq!(
+            Err(DialectError::UnknownCommand("#".to_string())),
+            Words::from_toml("\"#\" = \"debug\"")
+        );
+        assert_eq!(
+            Err(DialectError::MissingCommand('<')),
+            Words::from_toml("\"+\" = \"inc\"")
+        );
+    }
+}
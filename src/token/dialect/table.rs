@@ -0,0 +1,383 @@
+use super::DialectError;
+
+// the flat tables of strings Words is read from, as (key, value, line of the key) in file order.
+// the TOML is a document without tables, the JSON a single object
+pub type Pairs = Vec<(String, String, usize)>;
+
+pub fn toml(src: &str) -> Result<Pairs, DialectError> {
+    let mut c = Cursor::new(src);
+    let mut pairs = vec![];
+    loop {
+        c.skip(" \t");
+        match c.peek() {
+            None => break,
+            Some('\n') | Some('\r') => c.newline()?,
+            Some('#') => c.comment(),
+            Some('[') => return Err(c.error("expected a key, tables are not supported")),
+            Some(_) => {
+                let line = c.line;
+                let key = c.toml_key()?;
+                c.skip(" \t");
+                if c.peek() == Some('.') {
+                    return Err(c.error("expected '=', dotted keys are not supported"));
+                }
+                c.expect('=')?;
+                c.skip(" \t");
+                let value = c.toml_string()?;
+                c.skip(" \t");
+                if c.peek() == Some('#') {
+                    c.comment();
+                }
+                if c.peek().is_some() {
+                    c.newline()?;
+                }
+                pairs.push((key, value, line));
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+pub fn json(src: &str) -> Result<Pairs, DialectError> {
+    let mut c = Cursor::new(src);
+    let mut pairs = vec![];
+    c.skip(WHITESPACE);
+    c.expect('{')?;
+    c.skip(WHITESPACE);
+    if !c.eat("}") {
+        loop {
+            c.skip(WHITESPACE);
+            let line = c.line;
+            let key = c.json_string()?;
+            c.skip(WHITESPACE);
+            c.expect(':')?;
+            c.skip(WHITESPACE);
+            let value = c.json_string()?;
+            pairs.push((key, value, line));
+            c.skip(WHITESPACE);
+            if c.eat("}") {
+                break;
+            }
+            if !c.eat(",") {
+                return Err(c.error("expected ',' or '}'"));
+            }
+        }
+    }
+    c.skip(WHITESPACE);
+    if c.peek().is_some() {
+        return Err(c.error("expected the end of the file"));
+    }
+    Ok(pairs)
+}
+
+const WHITESPACE: &str = " \t\r\n";
+
+struct Cursor<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(src: &'a str) -> Self {
+        Self { rest: src, line: 1 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        if !self.rest.starts_with(s) {
+            return false;
+        }
+        for _ in s.chars() {
+            self.bump();
+        }
+        true
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), DialectError> {
+        match self.bump() {
+            Some(found) if found == c => Ok(()),
+            _ => Err(self.error(&format!("expected '{c}'"))),
+        }
+    }
+
+    fn skip(&mut self, chars: &str) {
+        while self.peek().map_or(false, |c| chars.contains(c)) {
+            self.bump();
+        }
+    }
+
+    fn error(&self, expected: &str) -> DialectError {
+        DialectError::Syntax(self.line, expected.to_string())
+    }
+
+    fn newline(&mut self) -> Result<(), DialectError> {
+        if self.eat("\n") || self.eat("\r\n") {
+            return Ok(());
+        }
+        Err(self.error("expected the end of the line"))
+    }
+
+    // up to the end of the line
+    fn comment(&mut self) {
+        while self.peek().map_or(false, |c| c != '\n' && c != '\r') {
+            self.bump();
+        }
+    }
+
+    fn toml_key(&mut self) -> Result<String, DialectError> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let bare = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+                let len = self.rest.find(|c| !bare(c)).unwrap_or(self.rest.len());
+                if len == 0 {
+                    return Err(self.error("expected a key"));
+                }
+                let key = self.rest[..len].to_string();
+                self.rest = &self.rest[len..];
+                Ok(key)
+            }
+        }
+    }
+
+    fn toml_string(&mut self) -> Result<String, DialectError> {
+        if self.eat("\"\"\"") {
+            self.multiline_basic_string()
+        } else if self.eat("'''") {
+            self.multiline_literal_string()
+        } else {
+            match self.peek() {
+                Some('"') => self.basic_string(),
+                Some('\'') => self.literal_string(),
+                _ => Err(self.error("expected a string")),
+            }
+        }
+    }
+
+    // "..." with the escapes of TOML
+    fn basic_string(&mut self) -> Result<String, DialectError> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape("btnfr\"\\", &[4, 8])?),
+                Some('\n') | None => return Err(self.error("expected '\"'")),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    // '...' as it is
+    fn literal_string(&mut self) -> Result<String, DialectError> {
+        self.expect('\'')?;
+        let len = self
+            .rest
+            .find(['\'', '\n'])
+            .filter(|&len| self.rest[len..].starts_with('\''))
+            .ok_or_else(|| self.error("expected \"'\""))?;
+        let s = self.rest[..len].to_string();
+        self.rest = &self.rest[len + 1..];
+        Ok(s)
+    }
+
+    // after the opening """, where a backslash at the end of a line trims the whitespace after it
+    fn multiline_basic_string(&mut self) -> Result<String, DialectError> {
+        let _ = self.eat("\n") || self.eat("\r\n");
+        let mut s = String::new();
+        loop {
+            if self.eat("\"\"\"") {
+                // up to two quotes right before the closing ones are in the string
+                while self.eat("\"") {
+                    s.push('"');
+                }
+                return Ok(s);
+            }
+            match self.bump() {
+                Some('\\')
+                    if self
+                        .rest
+                        .trim_start_matches([' ', '\t'])
+                        .starts_with(['\n', '\r']) =>
+                {
+                    self.skip(WHITESPACE);
+                }
+                Some('\\') => s.push(self.escape("btnfr\"\\", &[4, 8])?),
+                Some(c) => s.push(c),
+                None => return Err(self.error("expected '\"\"\"'")),
+            }
+        }
+    }
+
+    fn multiline_literal_string(&mut self) -> Result<String, DialectError> {
+        let _ = self.eat("\n") || self.eat("\r\n");
+        let len = self
+            .rest
+            .find("'''")
+            .ok_or_else(|| self.error("expected \"'''\""))?;
+        let mut s = self.rest[..len].to_string();
+        self.line += s.matches('\n').count();
+        self.rest = &self.rest[len + 3..];
+        while self.peek() == Some('\'') {
+            self.bump();
+            s.push('\'');
+        }
+        Ok(s)
+    }
+
+    // "..." with the escapes of JSON
+    fn json_string(&mut self) -> Result<String, DialectError> {
+        if self.peek() != Some('"') {
+            return Err(self.error("expected a string"));
+        }
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => s.push(self.escape("btnfr\"\\/", &[4])?),
+                Some(c) if c >= ' ' => s.push(c),
+                _ => return Err(self.error("expected '\"'")),
+            }
+        }
+    }
+
+    // the character after a backslash: one of `simple`, or u followed by 4 hex digits, or U by 8
+    // if 8 is in `hex`. a UTF-16 surrogate pair of two \u escapes is one character
+    fn escape(&mut self, simple: &str, hex: &[usize]) -> Result<char, DialectError> {
+        let c = match self.bump() {
+            Some(c) if simple.contains(c) => {
+                return Ok(match c {
+                    'b' => '\u{8}',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\u{c}',
+                    'r' => '\r',
+                    c => c,
+                })
+            }
+            Some(c) => c,
+            None => return Err(self.error("expected an escape")),
+        };
+        let digits = match c {
+            'u' if hex.contains(&4) => 4,
+            'U' if hex.contains(&8) => 8,
+            _ => return Err(self.error("expected a valid escape")),
+        };
+        let code = self.hex(digits)?;
+        let code = match code {
+            0xD800..=0xDBFF if digits == 4 && self.eat("\\u") => {
+                let low = self.hex(4)?;
+                if !(0xDC00..=0xDFFF).contains(&low) {
+                    return Err(self.error("expected a low surrogate"));
+                }
+                0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)
+            }
+            code => code,
+        };
+        char::from_u32(code).ok_or_else(|| self.error("expected a unicode scalar value"))
+    }
+
+    fn hex(&mut self, digits: usize) -> Result<u32, DialectError> {
+        let s = self.rest.get(..digits).unwrap_or_default();
+        if s.len() != digits || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(self.error("expected hex digits"));
+        }
+        self.rest = &self.rest[digits..];
+        Ok(u32::from_str_radix(s, 16).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str, usize)]) -> Pairs {
+        pairs
+            .iter()
+            .map(|&(k, v, line)| (k.to_string(), v.to_string(), line))
+            .collect()
+    }
+
+    #[test]
+    fn toml_strings() {
+        let src = "# comment\n\
+            \"+\" = \"a\\tb\\u00e9\\U0001F600\" # inline comment\n\
+            '-' = 'C:\\no\\escape'\r\n\
+            bare-key = \"\"\"\nfirst \\\n    second\"\"\"\n\
+            \"x\" = '''\nraw \\n\né'''\n\
+            \"y\" = \"\"";
+        assert_eq!(
+            Ok(pairs(&[
+                ("+", "a\tb\u{e9}\u{1F600}", 2),
+                ("-", "C:\\no\\escape", 3),
+                ("bare-key", "first second", 4),
+                ("x", "raw \\n\né", 7),
+                ("y", "", 10),
+            ])),
+            toml(src)
+        );
+    }
+
+    #[test]
+    fn toml_errors() {
+        for (src, line) in [
+            ("\"+\" = inc", 1),
+            ("\n\"+\" = \"inc", 2),
+            ("\"+\" = \"inc\" \"dec\"", 1),
+            ("[commands]\n\"+\" = \"inc\"", 1),
+            ("a.b = \"inc\"", 1),
+            ("\"+\" = \"\\q\"", 1),
+            ("\"+\" = 1", 1),
+        ] {
+            assert_eq!(
+                Some(line),
+                match toml(src) {
+                    Err(DialectError::Syntax(line, _)) => Some(line),
+                    _ => None,
+                },
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_strings() {
+        let src = "{\n  \"+\": \"inc\",\n  \"-\" : \"d\\u00e9c \\ud83d\\ude00 \\/\"\n}\n";
+        assert_eq!(
+            Ok(pairs(&[("+", "inc", 2), ("-", "d\u{e9}c \u{1F600} /", 3)])),
+            json(src)
+        );
+        assert_eq!(Ok(vec![]), json(" {} "));
+    }
+
+    #[test]
+    fn json_errors() {
+        for (src, line) in [
+            ("{\"+\": \"inc\",}", 1),
+            ("{\"+\": 1}", 1),
+            ("{\n\"+\": \"inc\"\n\"-\": \"dec\"}", 3),
+            ("{\"+\": \"inc\"} {}", 1),
+            ("[\"inc\"]", 1),
+            ("{\"+\": \"a\tb\"}", 1),
+        ] {
+            assert!(
+                matches!(json(src), Err(DialectError::Syntax(l, _)) if l == line),
+                "{src}"
+            );
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::bytecode::Inst;
+use crate::token::Span;
+use crate::vm::{Program, Step, VM};
+use std::{error, fmt, io};
+
+// the first line of a trace, followed by " pcs" if it has the executed pcs. each of the
+// following lines is an event as "p PC", "i BYTE" or "o BYTE"
+const HEADER: &str = "bf-jit trace";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Pc(usize), // about to execute
+    In(u8),    // read by GETC, EOF included
+    Out(u8),
+}
+
+impl Event {
+    fn parse(line: &str) -> Option<Self> {
+        let (tag, value) = line.split_once(' ')?;
+        match tag {
+            "p" => value.parse().ok().map(Event::Pc),
+            "i" => value.parse().ok().map(Event::In),
+            "o" => value.parse().ok().map(Event::Out),
+            _ => None,
+        }
+    }
+
+    fn encode(&self) -> String {
+        match self {
+            Event::Pc(pc) => format!("p {pc}\n"),
+            Event::In(c) => format!("i {c}\n"),
+            Event::Out(c) => format!("o {c}\n"),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Pc(pc) => write!(f, "pc {pc}"),
+            Event::In(c) => write!(f, "input {c}"),
+            Event::Out(c) => write!(f, "output {c}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub pcs: bool,
+    pub events: Vec<Event>,
+}
+
+impl Trace {
+    pub fn parse(src: &str) -> Result<Self, TraceError> {
+        let mut lines = src.lines();
+        let pcs = match lines.next() {
+            Some(HEADER) => false,
+            Some(line) if line.strip_prefix(HEADER) == Some(" pcs") => true,
+            _ => return Err(TraceError::Syntax(1)),
+        };
+        let events = lines
+            .enumerate()
+            .map(|(i, line)| Event::parse(line).ok_or(TraceError::Syntax(i + 2)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { pcs, events })
+    }
+
+    // the bytes read by GETC
+    fn input(&self) -> Vec<u8> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                Event::In(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+// run program to the end on vm without the JIT, writing the events to trace
+pub fn record<R: io::Read, W: io::Write>(
+    vm: &mut VM,
+    program: &Program,
+    reader: &mut R,
+    writer: &mut W,
+    trace: &mut dyn io::Write,
+    pcs: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    writeln!(trace, "{HEADER}{}", if pcs { " pcs" } else { "" })?;
+    let res = run(vm, program, reader, writer, pcs, &mut |_, event| {
+        trace.write_all(event.encode().as_bytes())?;
+        Ok(())
+    });
+    trace.flush()?;
+    res
+}
+
+// run program on vm with the input from trace, checking that the events are the same
+pub fn replay<W: io::Write>(
+    vm: &mut VM,
+    program: &Program,
+    trace: &Trace,
+    writer: &mut W,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut expected = trace.events.iter().copied().enumerate();
+    let input = trace.input();
+    let diverged = |index, pc, expected, found| {
+        TraceError::Diverged(Divergence {
+            index,
+            pc,
+            span: program.spans.get(pc).copied(),
+            expected,
+            found,
+        })
+    };
+
+    run(
+        vm,
+        program,
+        &mut input.as_slice(),
+        writer,
+        trace.pcs,
+        &mut |pc, found| match expected.next() {
+            Some((_, event)) if event == found => Ok(()),
+            Some((index, event)) => Err(diverged(index, pc, Some(event), Some(found)).into()),
+            None => Err(diverged(trace.events.len(), pc, None, Some(found)).into()),
+        },
+    )?;
+    match expected.next() {
+        Some((index, event)) => Err(diverged(index, vm.pc(), Some(event), None).into()),
+        None => Ok(()),
+    }
+}
+
+// run program to the end, passing the events with the pc they happened at to `on`
+fn run<R: io::Read, W: io::Write>(
+    vm: &mut VM,
+    program: &Program,
+    reader: &mut R,
+    writer: &mut W,
+    pcs: bool,
+    on: &mut dyn FnMut(usize, Event) -> Result<(), Box<dyn error::Error>>,
+) -> Result<(), Box<dyn error::Error>> {
+    let mut output = vec![];
+    while vm.pc() < program.bytecodes.len() {
+        let pc = vm.pc();
+        if pcs {
+            on(pc, Event::Pc(pc))?;
+        }
+        output.clear();
+        let res = vm.step(program, reader, &mut output);
+        let _ = writer.write_all(&output);
+        for &c in output.iter() {
+            on(pc, Event::Out(c))?;
+        }
+        if let Step::NeedInput = res? {
+            // no input for now is taken as the end of it, as in VM::run
+            vm.eof();
+        }
+        if program.bytecodes[pc] == Inst::GETC {
+            on(pc, Event::In(vm.mem()[vm.mem_ptr()]))?;
+        }
+    }
+    Ok(())
+}
+
+// the first event of a replay differing from the trace, None for the end of either
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub index: usize, // of the event in the trace
+    pub pc: usize,
+    pub span: Option<Span>,
+    pub expected: Option<Event>,
+    pub found: Option<Event>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceError {
+    Syntax(usize), // line
+    Diverged(Divergence),
+}
+
+impl TraceError {
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            TraceError::Diverged(divergence) => divergence.span,
+            TraceError::Syntax(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let describe = |event: &Option<Event>| match event {
+            Some(event) => event.to_string(),
+            None => "the end".to_string(),
+        };
+        match self {
+            TraceError::Syntax(line) => write!(f, "invalid trace at line {line}"),
+            TraceError::Diverged(Divergence {
+                index,
+                pc,
+                span,
+                expected,
+                found,
+            }) => {
+                if let Some(Span { line, col, .. }) = span {
+                    write!(f, "{line}:{col}: ")?;
+                }
+                write!(
+                    f,
+                    "replay diverged at event {index} (pc {pc}): expected {}, found {}",
+                    describe(expected),
+                    describe(found)
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for TraceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+
+    fn record_trace(bytecodes: Vec<Inst>, input: &str, pcs: bool) -> String {
+        let program = Program {
+            bytecodes,
+            ..Default::default()
+        };
+        let mut trace = vec![];
+        record(
+            &mut VM::for_program(&program),
+            &program,
+            &mut input.as_bytes(),
+            &mut vec![],
+            &mut trace,
+            pcs,
+        )
+        .unwrap();
+        String::from_utf8(trace).unwrap()
+    }
+
+    fn replay_trace(bytecodes: Vec<Inst>, trace: &str) -> Result<Vec<u8>, String> {
+        let program = Program {
+            bytecodes,
+            ..Default::default()
+        };
+        let mut output = vec![];
+        replay(
+            &mut VM::for_program(&program),
+            &program,
+            &Trace::parse(trace).map_err(|e| e.to_string())?,
+            &mut output,
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(output)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        // ",+."
+        let bytecodes = vec![GETC, ADD(1), PUTC];
+        let trace = record_trace(bytecodes.clone(), "a", true);
+        assert_eq!("bf-jit trace pcs\np 0\ni 97\np 1\np 2\no 98\n", trace);
+        assert_eq!(Ok(b"b".to_vec()), replay_trace(bytecodes, &trace));
+    }
+
+    #[test]
+    fn replay_diverged() {
+        let trace = record_trace(vec![GETC, ADD(1), PUTC], "a", false);
+        assert_eq!(
+            Err("replay diverged at event 1 (pc 2): expected output 98, found output 99".into()),
+            replay_trace(vec![GETC, ADD(2), PUTC], &trace)
+        );
+        assert_eq!(
+            Err("replay diverged at event 1 (pc 2): expected output 98, found the end".into()),
+            replay_trace(vec![GETC, ADD(1)], &trace)
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        assert_eq!(Err(TraceError::Syntax(1)), Trace::parse("trace\n"));
+        assert_eq!(
+            Err(TraceError::Syntax(3)),
+            Trace::parse("bf-jit trace\ni 1\nx 2\n")
+        );
+    }
+}
@@ -1,21 +1,108 @@
-use crate::bytecode::Inst;
+use crate::bytecode::{self, Inst, OptLevel};
+use crate::engine::Engine;
 use crate::jit;
+use crate::token::Span;
 use std::error;
 use std::fmt;
 use std::io;
+use std::time::{Duration, Instant};
+
+mod preeval;
+mod snapshot;
+
+pub use self::preeval::{preeval, PREEVAL_STEPS};
+pub use self::snapshot::{Snapshot, SnapshotError};
 
 pub const MEMSIZE: usize = 100000;
 pub const JIT_EXEC_TH: u8 = 5;
 pub const EOF: u8 = 0;
 
+#[derive(Debug, Clone)]
 pub struct Program {
     pub bytecodes: Vec<Inst>,
+    pub spans: Vec<Span>, // source of each bytecode, may be empty
+    // tape state the program starts from (set by preeval)
+    pub init: Option<TapeInit>,
+    pub level: OptLevel, // the bytecodes are optimized up to, NONE as written
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self {
+            bytecodes: vec![],
+            spans: vec![],
+            init: None,
+            level: OptLevel::NONE,
+        }
+    }
+}
+
+impl Program {
+    // optimize again up to level, e.g. a program from `parse`. the values known at compile time
+    // assume a zero tape, so a program with init is only optimized up to PEEPHOLE
+    pub fn optimize(self, level: OptLevel) -> Self {
+        let level = match self.init {
+            Some(_) => level.min(OptLevel::PEEPHOLE),
+            None => level,
+        };
+        let block = bytecode::raise(&self.bytecodes, &self.spans);
+        let (bytecodes, spans) = bytecode::lower(bytecode::optimize_with(block, level));
+        Self {
+            bytecodes,
+            spans: if self.spans.is_empty() { vec![] } else { spans },
+            init: self.init,
+            level: level.max(self.level),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TapeInit {
+    pub mem_ptr: usize,
+    pub cells: Vec<(usize, u8)>, // non-zero cells as (addr, value)
+    pub steps: u64,              // run to get here, counted against Limits::max_steps
+}
+
+// where run_for stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Finished,
+    OutOfFuel,
+    NeedInput, // the reader would block at GETC, which is retried on resuming
+}
+
+// the result of executing one instruction
+pub(crate) enum Step {
+    Next,
+    NeedInput, // pc stays at the GETC
+}
+
+// execution limits, enforced from VM::limit on
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_steps: Option<u64>, // instructions, including those run by preeval
+    pub timeout: Option<Duration>,
+    pub max_output: Option<usize>, // bytes
 }
 
+impl Limits {
+    // whether any limit is set, for which the JIT generates the checks
+    pub fn any(&self) -> bool {
+        self.max_steps.is_some() || self.timeout.is_some() || self.max_output.is_some()
+    }
+}
+
+// how often the interpreter checks the deadline, in steps
+const DEADLINE_INTERVAL: u64 = 1 << 12;
+
 pub struct VM {
     mem: [u8; MEMSIZE],
     mem_ptr: usize,
     pc: usize,
+    limits: Limits,
+    deadline: Option<Instant>,
+    steps: u64,
+    output: usize,
 }
 
 impl Default for VM {
@@ -24,6 +111,10 @@ impl Default for VM {
             mem: [0; MEMSIZE],
             mem_ptr: MEMSIZE / 2,
             pc: 0,
+            limits: Limits::default(),
+            deadline: None,
+            steps: 0,
+            output: 0,
         }
     }
 }
@@ -33,77 +124,300 @@ impl VM {
         Self::default()
     }
 
+    // a VM starting from the tape of program
+    pub fn for_program(program: &Program) -> Self {
+        let mut vm = Self::new();
+        if let Some(init) = &program.init {
+            vm.load(init);
+        }
+        vm
+    }
+
+    // start the clock of limits.timeout
+    pub fn limit(&mut self, limits: Limits) {
+        self.limit_since(limits, Instant::now());
+    }
+
+    // the same with the clock started at start, e.g. before compiling with preeval
+    pub fn limit_since(&mut self, limits: Limits, start: Instant) {
+        self.limits = limits;
+        self.deadline = limits.timeout.map(|timeout| start + timeout);
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn mem_ptr(&self) -> usize {
+        self.mem_ptr
+    }
+
+    pub fn mem(&self) -> &[u8] {
+        &self.mem
+    }
+
+    // instructions run so far by any engine, including those of preeval
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn load(&mut self, init: &TapeInit) {
+        self.mem_ptr = init.mem_ptr;
+        self.steps = init.steps;
+        for &(addr, v) in init.cells.iter() {
+            self.mem[addr] = v;
+        }
+    }
+
+    // run program to the end with engine
     pub fn run<R: io::Read, W: io::Write>(
         &mut self,
         program: &Program,
         reader: &mut R,
         writer: &mut W,
-        enable_jit: bool,
+        engine: &dyn Engine,
+    ) -> Result<(), RuntimeError> {
+        engine.run(self, program, reader, writer)
+    }
+
+    pub(crate) fn interpret(
+        &mut self,
+        program: &Program,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
+    ) -> Result<(), RuntimeError> {
+        while self.pc < program.bytecodes.len() {
+            if let Step::NeedInput = self.step(program, reader, writer)? {
+                // no input for now is taken as the end of it
+                self.eof();
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run_jit(
+        &mut self,
+        program: &Program,
+        reader: &mut dyn io::Read,
+        writer: &mut dyn io::Write,
     ) -> Result<(), RuntimeError> {
         let jit = jit::JIT::new();
 
         while self.pc < program.bytecodes.len() {
-            if enable_jit && self.check_exec_count() > JIT_EXEC_TH {
+            if self.check_exec_count() > JIT_EXEC_TH {
                 let start = self.pc;
                 // TODO: ループ単位でやる
                 let end = program.bytecodes.len() - 1;
-                let next_mem_ptr: usize;
-                unsafe {
-                    next_mem_ptr =
-                        jit.enter(&program.bytecodes, start, end, &self.mem, self.mem_ptr);
-                }
-                self.mem_ptr = next_mem_ptr;
-                self.pc = end + 1;
-                continue;
-            }
-            match program.bytecodes[self.pc] {
-                Inst::MOVPTR(v) => {
-                    self.mem_ptr = check_memory_bound(self.mem_ptr as isize + v, MEMSIZE)?;
-                }
-                Inst::ADD(v) => {
-                    self.mem[self.mem_ptr] = self.mem[self.mem_ptr].wrapping_add(v as u8);
-                }
-                Inst::SETZERO => {
-                    self.mem[self.mem_ptr] = 0;
-                }
-                Inst::MULINTO(coef, offset) => {
-                    let mem_ptr_to = check_memory_bound(self.mem_ptr as isize + offset, MEMSIZE)?;
-                    self.mem[mem_ptr_to] = self.mem[mem_ptr_to]
-                        .wrapping_add((coef * self.mem[self.mem_ptr] as isize) as u8);
-                    self.mem[self.mem_ptr] = 0;
-                }
-                Inst::FINDZERO(offset) => {
-                    while self.mem[self.mem_ptr] != 0 {
-                        self.mem_ptr = check_memory_bound(self.mem_ptr as isize + offset, MEMSIZE)?;
+                let mut io = jit::IO {
+                    reader,
+                    writer,
+                    budget: self.budget(),
+                    steps: 0,
+                    output: 0,
+                };
+                let res = unsafe {
+                    jit.enter(
+                        &program.bytecodes,
+                        start,
+                        end,
+                        &self.mem,
+                        self.mem_ptr,
+                        &mut io,
+                    )
+                };
+                self.steps += io.steps;
+                self.output += io.output;
+                let (pc, e) = match res {
+                    Ok(next_mem_ptr) => {
+                        self.mem_ptr = next_mem_ptr;
+                        self.pc = end + 1;
+                        continue;
                     }
-                }
-                Inst::PUTC => {
-                    let _ = writer.write(&self.mem[self.mem_ptr..(self.mem_ptr + 1)]);
-                }
-                Inst::GETC => {
-                    if reader
-                        .read_exact(std::slice::from_mut(&mut self.mem[self.mem_ptr]))
-                        .is_err()
-                    {
-                        self.mem[self.mem_ptr] = EOF;
+                    Err(jit::Abort::Memory { pc, mem_ptr, ptr }) => {
+                        self.mem_ptr = mem_ptr;
+                        (pc, RuntimeError::out_of_range(ptr))
                     }
-                }
-                Inst::JZ(addr) => {
-                    if self.mem[self.mem_ptr] == 0 {
-                        self.pc = addr;
-                        continue;
+                    Err(jit::Abort::Limit { pc, mem_ptr, limit }) => {
+                        self.mem_ptr = mem_ptr;
+                        (pc, RuntimeError::limit(limit))
                     }
+                };
+                self.pc = pc;
+                return Err(e.at(pc, program, &self.mem));
+            }
+            if let Step::NeedInput = self.step(program, reader, writer)? {
+                // no input for now is taken as the end of it
+                self.eof();
+            }
+        }
+        Ok(())
+    }
+
+    // finish the GETC waiting for input with EOF
+    pub(crate) fn eof(&mut self) {
+        self.mem[self.mem_ptr] = EOF;
+        self.pc += 1;
+    }
+
+    // run at most `fuel` instructions without the JIT, and return why it stopped.
+    // pc, the pointer and the tape are kept, so that calling it again resumes the program
+    pub fn run_for<R: io::Read, W: io::Write>(
+        &mut self,
+        program: &Program,
+        reader: &mut R,
+        writer: &mut W,
+        fuel: usize,
+    ) -> Result<Status, RuntimeError> {
+        for _ in 0..fuel {
+            if self.pc >= program.bytecodes.len() {
+                return Ok(Status::Finished);
+            }
+            if let Step::NeedInput = self.step(program, reader, writer)? {
+                return Ok(Status::NeedInput);
+            }
+        }
+        if self.pc >= program.bytecodes.len() {
+            return Ok(Status::Finished);
+        }
+        Ok(Status::OutOfFuel)
+    }
+
+    // execute the instruction at pc
+    pub(crate) fn step<R: io::Read + ?Sized, W: io::Write + ?Sized>(
+        &mut self,
+        program: &Program,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<Step, RuntimeError> {
+        self.count_step()
+            .and_then(|()| self.exec(program, reader, writer))
+            .map_err(|e| e.at(self.pc, program, &self.mem))
+    }
+
+    fn count_step(&mut self) -> Result<(), RuntimeError> {
+        if self.limits.max_steps.map_or(false, |max| self.steps >= max) {
+            return Err(RuntimeError::limit(jit::Limit::Steps));
+        }
+        self.steps += 1;
+        if let Some(deadline) = self.deadline {
+            if self.steps % DEADLINE_INTERVAL == 0 && Instant::now() >= deadline {
+                return Err(RuntimeError::limit(jit::Limit::Time));
+            }
+        }
+        Ok(())
+    }
+
+    // the limits left for the JIT, None without any
+    fn budget(&self) -> Option<jit::Budget> {
+        if !self.limits.any() {
+            return None;
+        }
+        let Limits {
+            max_steps,
+            max_output,
+            ..
+        } = self.limits;
+        Some(jit::Budget {
+            steps: max_steps.map(|max| max.saturating_sub(self.steps)),
+            deadline: self.deadline,
+            output: max_output.map(|max| max.saturating_sub(self.output)),
+            exceeded: None,
+        })
+    }
+
+    // write s within limits.max_output
+    fn write<W: io::Write + ?Sized>(
+        &mut self,
+        writer: &mut W,
+        s: &[u8],
+    ) -> Result<(), RuntimeError> {
+        let room = match self.limits.max_output {
+            Some(max) => max.saturating_sub(self.output).min(s.len()),
+            None => s.len(),
+        };
+        let _ = writer.write_all(&s[..room]);
+        self.output += room;
+        if room < s.len() {
+            return Err(RuntimeError::limit(jit::Limit::Output));
+        }
+        Ok(())
+    }
+
+    fn exec<R: io::Read + ?Sized, W: io::Write + ?Sized>(
+        &mut self,
+        program: &Program,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<Step, RuntimeError> {
+        match program.bytecodes[self.pc] {
+            Inst::MOVPTR(v) => {
+                self.mem_ptr = check_memory_bound(self.mem_ptr as isize + v, MEMSIZE)?;
+            }
+            Inst::ADD(v) => {
+                self.mem[self.mem_ptr] = self.mem[self.mem_ptr].wrapping_add(v as u8);
+            }
+            Inst::SETZERO => {
+                self.mem[self.mem_ptr] = 0;
+            }
+            Inst::SET(v) => {
+                self.mem[self.mem_ptr] = v;
+            }
+            Inst::MULINTO(coef, offset) => {
+                let mem_ptr_to = check_memory_bound(self.mem_ptr as isize + offset, MEMSIZE)?;
+                self.mem[mem_ptr_to] = self.mem[mem_ptr_to]
+                    .wrapping_add((coef * self.mem[self.mem_ptr] as isize) as u8);
+                self.mem[self.mem_ptr] = 0;
+            }
+            Inst::FINDZERO(offset) => {
+                while self.mem[self.mem_ptr] != 0 {
+                    self.mem_ptr = check_memory_bound(self.mem_ptr as isize + offset, MEMSIZE)?;
                 }
-                Inst::JNZ(addr) => {
-                    if self.mem[self.mem_ptr] != 0 {
-                        self.pc = addr;
-                        continue;
+            }
+            Inst::SCAN(ref adds, step) => {
+                while self.mem[self.mem_ptr] != 0 {
+                    for &(offset, v) in adds.iter() {
+                        let mem_ptr_to =
+                            check_memory_bound(self.mem_ptr as isize + offset, MEMSIZE)?;
+                        self.mem[mem_ptr_to] = self.mem[mem_ptr_to].wrapping_add(v as u8);
                     }
+                    self.mem_ptr = check_memory_bound(self.mem_ptr as isize + step, MEMSIZE)?;
+                }
+            }
+            Inst::PUTC => {
+                let c = self.mem[self.mem_ptr];
+                self.write(writer, &[c])?;
+            }
+            Inst::PRINT(ref s) => {
+                self.write(writer, s)?;
+            }
+            Inst::GETC => {
+                let mut buf = [0];
+                self.mem[self.mem_ptr] = match reader.read_exact(&mut buf) {
+                    Ok(()) => buf[0],
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(Step::NeedInput),
+                    Err(_) => EOF,
+                };
+            }
+            Inst::DEBUG => {
+                // keep the order with the output so far
+                let _ = writer.flush();
+                eprintln!("{}", dump(&self.mem, self.mem_ptr));
+            }
+            Inst::JZ(addr) => {
+                if self.mem[self.mem_ptr] == 0 {
+                    self.pc = addr;
+                    return Ok(Step::Next);
+                }
+            }
+            Inst::JNZ(addr) => {
+                if self.mem[self.mem_ptr] != 0 {
+                    self.pc = addr;
+                    return Ok(Step::Next);
                 }
             }
-            self.pc += 1;
         }
-        Ok(())
+        self.pc += 1;
+        Ok(Step::Next)
     }
 
     fn check_exec_count(&mut self) -> u8 {
@@ -112,34 +426,155 @@ impl VM {
     }
 }
 
+// the output of DEBUG
+pub fn dump(mem: &[u8], mem_ptr: usize) -> String {
+    format!("#{mem_ptr}: {}", TapeWindow::around(mem, mem_ptr as isize))
+}
+
 #[inline(always)]
 fn check_memory_bound(v: isize, ceil: usize) -> Result<usize, RuntimeError> {
     if v < 0 || ceil as isize <= v {
-        return Err(RuntimeError::MemoryOutofRange);
+        return Err(RuntimeError::out_of_range(v));
     }
     Ok(v as usize)
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeError {
-    MemoryOutofRange,
+    // the instruction at pc moved or accessed the pointer to ptr, outside the tape
+    MemoryOutofRange {
+        pc: usize,
+        span: Option<Span>,
+        ptr: isize,
+        tape: TapeWindow,
+    },
+    // Limits::max_steps ran out before the instruction at pc
+    StepLimit {
+        pc: usize,
+        span: Option<Span>,
+    },
+    TimeLimit {
+        pc: usize,
+        span: Option<Span>,
+    },
+    // the instruction at pc wrote past Limits::max_output
+    OutputLimit {
+        pc: usize,
+        span: Option<Span>,
+    },
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Option<Span> {
+        use self::RuntimeError::*;
+        match self {
+            MemoryOutofRange { span, .. }
+            | StepLimit { span, .. }
+            | TimeLimit { span, .. }
+            | OutputLimit { span, .. } => *span,
+        }
+    }
+
+    pub fn pc(&self) -> usize {
+        use self::RuntimeError::*;
+        match self {
+            MemoryOutofRange { pc, .. }
+            | StepLimit { pc, .. }
+            | TimeLimit { pc, .. }
+            | OutputLimit { pc, .. } => *pc,
+        }
+    }
+
+    fn out_of_range(ptr: isize) -> Self {
+        RuntimeError::MemoryOutofRange {
+            pc: 0,
+            span: None,
+            ptr,
+            tape: TapeWindow::default(),
+        }
+    }
+
+    fn limit(limit: jit::Limit) -> Self {
+        let (pc, span) = (0, None);
+        match limit {
+            jit::Limit::Steps => RuntimeError::StepLimit { pc, span },
+            jit::Limit::Time => RuntimeError::TimeLimit { pc, span },
+            jit::Limit::Output => RuntimeError::OutputLimit { pc, span },
+        }
+    }
+
+    // fill in where the error happened
+    fn at(self, pc: usize, program: &Program, mem: &[u8]) -> Self {
+        use self::RuntimeError::*;
+        let span = program.spans.get(pc).copied();
+        match self {
+            MemoryOutofRange { ptr, .. } => MemoryOutofRange {
+                pc,
+                span,
+                ptr,
+                tape: TapeWindow::around(mem, ptr),
+            },
+            StepLimit { .. } => StepLimit { pc, span },
+            TimeLimit { .. } => TimeLimit { pc, span },
+            OutputLimit { .. } => OutputLimit { pc, span },
+        }
+    }
 }
 
 impl fmt::Display for RuntimeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::RuntimeError::*;
+        if let Some(Span { line, col, .. }) = self.span() {
+            write!(f, "{line}:{col}: ")?;
+        }
         match self {
-            MemoryOutofRange => write!(f, "memory out of range"),
+            MemoryOutofRange { pc, ptr, .. } => write!(
+                f,
+                "memory out of range: pointer {ptr} is outside 0..{MEMSIZE} (pc {pc})"
+            ),
+            StepLimit { pc, .. } => write!(f, "step limit exceeded (pc {pc})"),
+            TimeLimit { pc, .. } => write!(f, "time limit exceeded (pc {pc})"),
+            OutputLimit { pc, .. } => write!(f, "output limit exceeded (pc {pc})"),
         }
     }
 }
 
+const TAPE_WINDOW: usize = 4; // cells on each side
+
+// cells of the tape from start
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TapeWindow {
+    pub start: usize,
+    pub cells: Vec<u8>,
+}
+
+impl TapeWindow {
+    // cells around ptr, clamped to the tape
+    pub(crate) fn around(mem: &[u8], ptr: isize) -> Self {
+        let center = ptr.clamp(0, mem.len() as isize - 1) as usize;
+        let start = center.saturating_sub(TAPE_WINDOW);
+        let end = (center + TAPE_WINDOW + 1).min(mem.len());
+        Self {
+            start,
+            cells: mem[start..end].to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for TapeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let end = self.start + self.cells.len();
+        write!(f, "tape[{}..{end}] = {:?}", self.start, self.cells)
+    }
+}
+
 impl error::Error for RuntimeError {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::bytecode::Inst::*;
+    use crate::engine::{Interpreter, Jit};
 
     #[test]
     fn run_hello_world() {
@@ -192,14 +627,16 @@ mod tests {
         ];
         let mut vm = VM::new();
         let mut output = vec![];
-        let _ = vm
-            .run(
-                &Program { bytecodes },
-                &mut "".as_bytes(),
-                &mut output,
-                false,
-            )
-            .unwrap();
+        vm.run(
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
+            &mut "".as_bytes(),
+            &mut output,
+            &Interpreter,
+        )
+        .unwrap();
         assert_eq!(
             "Hello, World!"
                 .chars()
@@ -217,14 +654,16 @@ mod tests {
             mem_ptr: 0,
             ..Default::default()
         };
-        let _ = vm
-            .run(
-                &Program { bytecodes },
-                &mut "".as_bytes(),
-                &mut vec![],
-                false,
-            )
-            .unwrap();
+        vm.run(
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
+            &mut "".as_bytes(),
+            &mut vec![],
+            &Interpreter,
+        )
+        .unwrap();
         assert_eq!(vm.mem[0..3], [0, 0, 10]);
     }
 
@@ -239,9 +678,16 @@ mod tests {
 
         let mut input = text.as_bytes();
         let mut output = vec![];
-        let _ = vm
-            .run(&Program { bytecodes }, &mut input, &mut output, false)
-            .unwrap();
+        vm.run(
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
+            &mut input,
+            &mut output,
+            &Interpreter,
+        )
+        .unwrap();
         assert_eq!(text.chars().map(|c| c as u8).collect::<Vec<u8>>(), output);
     }
 
@@ -250,13 +696,236 @@ mod tests {
         let bytecodes = vec![MOVPTR(MEMSIZE as isize)];
         let mut vm = VM::new();
         let res = vm.run(
-            &Program { bytecodes },
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
+            &mut "".as_bytes(),
+            &mut vec![],
+            &Interpreter,
+        );
+
+        assert_eq!(
+            Some(RuntimeError::MemoryOutofRange {
+                pc: 0,
+                span: None,
+                ptr: (MEMSIZE / 2 + MEMSIZE) as isize,
+                tape: TapeWindow {
+                    start: MEMSIZE - 5,
+                    cells: vec![0; 5],
+                }
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn run_out_of_range_tape() {
+        let bytecodes = vec![ADD(1), MOVPTR(2), MOVPTR(MEMSIZE as isize)];
+        let mut vm = VM {
+            mem_ptr: MEMSIZE - 3,
+            ..Default::default()
+        };
+        let res = vm.run(
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
             &mut "".as_bytes(),
             &mut vec![],
-            false,
+            &Interpreter,
         );
 
-        assert_eq!(Some(RuntimeError::MemoryOutofRange), res.err());
+        let mut cells = [0; 5];
+        cells[2] = 1;
+        assert_eq!(
+            Some(RuntimeError::MemoryOutofRange {
+                pc: 2,
+                span: None,
+                ptr: (2 * MEMSIZE - 1) as isize,
+                tape: TapeWindow {
+                    start: MEMSIZE - 5,
+                    cells: cells.to_vec(),
+                }
+            }),
+            res.err()
+        );
+    }
+
+    #[test]
+    fn run_for_resume() {
+        // "+++[.-]"
+        let program = Program {
+            bytecodes: vec![ADD(3), JZ(5), PUTC, ADD(-1), JNZ(2)],
+            ..Default::default()
+        };
+        let mut vm = VM::for_program(&program);
+        let mut output = vec![];
+        let mut run = |fuel| vm.run_for(&program, &mut "".as_bytes(), &mut output, fuel);
+        assert_eq!(Ok(Status::OutOfFuel), run(4));
+        assert_eq!(Ok(Status::OutOfFuel), run(3));
+        assert_eq!(Ok(Status::Finished), run(5));
+        assert_eq!(Ok(Status::Finished), run(1));
+        assert_eq!(vec![3, 2, 1], output);
+    }
+
+    #[test]
+    fn run_for_then_jit() {
+        // "+++[>.<-]" paused at every pc, also in the loop, and finished by each engine
+        let program = crate::parse("+++[>.<-]").unwrap();
+        for fuel in 1..19 {
+            let limits = [
+                Limits::default(),
+                Limits {
+                    max_steps: Some(1000),
+                    ..Default::default()
+                },
+            ];
+            for limits in limits {
+                let results: Vec<_> = [&Interpreter as &dyn Engine, &Jit]
+                    .iter()
+                    .map(|&engine| {
+                        let mut vm = VM::for_program(&program);
+                        vm.limit(limits);
+                        let mut output = vec![];
+                        let status = vm.run_for(&program, &mut io::empty(), &mut output, fuel);
+                        assert_eq!(Ok(Status::OutOfFuel), status, "{fuel}");
+                        let res = vm.run(&program, &mut io::empty(), &mut output, engine);
+                        (res, output, vm.snapshot())
+                    })
+                    .collect();
+                assert_eq!(results[0], results[1], "{fuel}");
+                assert_eq!(vec![0, 0, 0], results[1].1);
+            }
+        }
+    }
+
+    // gives the bytes pushed so far, and WouldBlock when there are none
+    struct Pipe(Vec<u8>);
+
+    impl io::Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            let n = buf.len().min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn run_step_limit() {
+        // "+[]"
+        let program = Program {
+            bytecodes: vec![ADD(1), JZ(3), JNZ(2)],
+            ..Default::default()
+        };
+        for engine in [&Interpreter as &dyn Engine, &Jit] {
+            let mut vm = VM::for_program(&program);
+            vm.limit(Limits {
+                max_steps: Some(100_000),
+                ..Default::default()
+            });
+            let res = vm.run(&program, &mut "".as_bytes(), &mut vec![], engine);
+            assert!(matches!(res, Err(RuntimeError::StepLimit { pc: 2, .. })));
+            assert_eq!(100_000, vm.steps());
+        }
+    }
+
+    #[test]
+    fn run_output_limit() {
+        // "+[.]"
+        let program = Program {
+            bytecodes: vec![ADD(1), JZ(4), PUTC, JNZ(2)],
+            ..Default::default()
+        };
+        for engine in [&Interpreter as &dyn Engine, &Jit] {
+            let mut vm = VM::for_program(&program);
+            vm.limit(Limits {
+                max_output: Some(10),
+                ..Default::default()
+            });
+            let mut output = vec![];
+            let res = vm.run(&program, &mut "".as_bytes(), &mut output, engine);
+            assert!(matches!(res, Err(RuntimeError::OutputLimit { .. })));
+            assert_eq!(vec![1; 10], output);
+        }
+    }
+
+    #[test]
+    fn run_limits_stop_in_place() {
+        // "+[.>+]" and "+[>+]", stopped by the JIT where the interpreter stops
+        let putc = vec![ADD(1), JZ(6), PUTC, MOVPTR(1), ADD(1), JNZ(2)];
+        let plain = vec![ADD(1), JZ(5), MOVPTR(1), ADD(1), JNZ(2)];
+        for (bytecodes, limits, pc) in [
+            (
+                putc,
+                Limits {
+                    max_output: Some(3),
+                    ..Default::default()
+                },
+                2,
+            ),
+            (
+                plain,
+                Limits {
+                    max_steps: Some(302),
+                    ..Default::default()
+                },
+                2,
+            ),
+        ] {
+            let program = Program {
+                bytecodes,
+                ..Default::default()
+            };
+            let stop = |engine: &dyn Engine| {
+                let mut vm = VM::for_program(&program);
+                vm.limit(limits);
+                let mut output = vec![];
+                let res = vm.run(&program, &mut "".as_bytes(), &mut output, engine);
+                (res.map_err(|e| e.pc()), vm.snapshot(), output)
+            };
+            let (res, snapshot, output) = stop(&Interpreter);
+            assert_eq!(Err(pc), res);
+            assert_eq!((res, snapshot, output), stop(&Jit));
+        }
+    }
+
+    #[test]
+    fn run_for_need_input() {
+        // ",.,."
+        let program = Program {
+            bytecodes: vec![GETC, PUTC, GETC, PUTC],
+            ..Default::default()
+        };
+        let mut vm = VM::for_program(&program);
+        let (mut input, mut output) = (Pipe(vec![]), vec![]);
+        assert_eq!(
+            Ok(Status::NeedInput),
+            vm.run_for(&program, &mut input, &mut output, 100)
+        );
+        input.0.push(b'a');
+        assert_eq!(
+            Ok(Status::NeedInput),
+            vm.run_for(&program, &mut input, &mut output, 100)
+        );
+        assert_eq!(2, vm.pc());
+        input.0.push(b'b');
+        assert_eq!(
+            Ok(Status::Finished),
+            vm.run_for(&program, &mut input, &mut output, 100)
+        );
+        assert_eq!(b"ab".to_vec(), output);
+    }
+
+    #[test]
+    fn dump_around_pointer() {
+        let mut mem = [0; MEMSIZE];
+        mem[1] = 7;
+        assert_eq!("#1: tape[0..6] = [0, 7, 0, 0, 0, 0]", dump(&mem, 1));
     }
 
     #[test]
@@ -274,14 +943,16 @@ mod tests {
             mem_ptr: 0,
             ..Default::default()
         };
-        let _ = vm
-            .run(
-                &Program { bytecodes },
-                &mut "".as_bytes(),
-                &mut vec![],
-                false,
-            )
-            .unwrap();
+        vm.run(
+            &Program {
+                bytecodes,
+                ..Default::default()
+            },
+            &mut "".as_bytes(),
+            &mut vec![],
+            &Interpreter,
+        )
+        .unwrap();
         assert_eq!(vm.mem[0..4], [1, 2, 3, 0]);
         assert_eq!(vm.mem_ptr, 3)
     }
@@ -0,0 +1,209 @@
+use super::{Limits, Program, TapeInit, VM};
+use crate::bytecode::Inst;
+use std::io;
+use std::time::Instant;
+
+pub const PREEVAL_STEPS: usize = 1_000_000;
+
+// run the program at compile time until the first GETC or DEBUG (or `budget` steps),
+// and replace the executed prefix with its output and the resulting tape. the steps and the time
+// taken are within limits from start, as those of the run time
+pub fn preeval(program: Program, budget: usize, limits: Limits, start: Instant) -> Program {
+    let insts = &program.bytecodes;
+
+    // loop depth of each instruction, resuming is only possible at depth 0
+    let mut depths = vec![0; insts.len() + 1];
+    for (addr, inst) in insts.iter().enumerate() {
+        if let Inst::JZ(end) = inst {
+            depths[addr + 1] += 1;
+            depths[*end] -= 1;
+        }
+    }
+    for addr in 1..depths.len() {
+        depths[addr] += depths[addr - 1];
+    }
+
+    // find the last point at the top level reached before stopping
+    let mut vm = VM::for_program(&program);
+    vm.limit_since(limits, start);
+    let (mut steps, mut resume) = (0, None);
+    loop {
+        if depths[vm.pc] == 0 {
+            resume = Some((steps, vm.pc));
+        }
+        if vm.pc >= insts.len()
+            || steps >= budget
+            || matches!(insts[vm.pc], Inst::GETC | Inst::DEBUG)
+        {
+            break;
+        }
+        // leave runtime errors to the run time
+        if vm
+            .step(&program, &mut io::empty(), &mut io::sink())
+            .is_err()
+        {
+            break;
+        }
+        steps += 1;
+    }
+    let (steps, pc) = match resume {
+        Some((steps, pc)) if steps > 0 => (steps, pc),
+        _ => return program,
+    };
+
+    // the execution is deterministic as no input is read, so replay it up to there
+    let mut vm = VM::for_program(&program);
+    let mut output = vec![];
+    for _ in 0..steps {
+        let _ = vm.step(&program, &mut io::empty(), &mut output);
+    }
+
+    let mut bytecodes = vec![];
+    let mut spans = vec![];
+    if !output.is_empty() {
+        bytecodes.push(Inst::PRINT(output));
+        // the whole prefix evaluated
+        if let (Some(&first), Some(&last)) = (program.spans.first(), program.spans.get(pc - 1)) {
+            spans.push(first.to(last));
+        }
+    }
+    spans.extend(program.spans.iter().skip(pc));
+    let base = bytecodes.len();
+    for inst in program.bytecodes.into_iter().skip(pc) {
+        bytecodes.push(match inst {
+            Inst::JZ(addr) => Inst::JZ(addr - pc + base),
+            Inst::JNZ(addr) => Inst::JNZ(addr - pc + base),
+            inst => inst,
+        });
+    }
+
+    let cells = vm
+        .mem
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v != 0)
+        .map(|(addr, &v)| (addr, v))
+        .collect();
+    Program {
+        bytecodes,
+        spans,
+        init: Some(TapeInit {
+            mem_ptr: vm.mem_ptr,
+            cells,
+            steps: vm.steps,
+        }),
+        level: program.level,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use crate::vm::MEMSIZE;
+
+    #[test]
+    fn preeval_without_input() {
+        // "++++++++[>++++++++<-]>+.+."
+        let bytecodes = vec![ADD(8), MULINTO(8, 1), MOVPTR(1), ADD(1), PUTC, ADD(1), PUTC];
+        let program = preeval(
+            Program {
+                bytecodes,
+                ..Default::default()
+            },
+            PREEVAL_STEPS,
+            Limits::default(),
+            Instant::now(),
+        );
+        assert_eq!(vec![PRINT(b"AB".to_vec())], program.bytecodes);
+        assert_eq!(
+            Some(TapeInit {
+                mem_ptr: MEMSIZE / 2 + 1,
+                cells: vec![(MEMSIZE / 2 + 1, 66)],
+                steps: 7,
+            }),
+            program.init
+        );
+    }
+
+    #[test]
+    fn preeval_until_getc() {
+        // "+++.>,[.,]"
+        let bytecodes = vec![ADD(3), PUTC, MOVPTR(1), GETC, JZ(8), PUTC, GETC, JNZ(5)];
+        let program = preeval(
+            Program {
+                bytecodes,
+                ..Default::default()
+            },
+            PREEVAL_STEPS,
+            Limits::default(),
+            Instant::now(),
+        );
+        assert_eq!(
+            vec![PRINT(vec![3]), GETC, JZ(6), PUTC, GETC, JNZ(3)],
+            program.bytecodes
+        );
+        assert_eq!(
+            Some(TapeInit {
+                mem_ptr: MEMSIZE / 2 + 1,
+                cells: vec![(MEMSIZE / 2, 3)],
+                steps: 3,
+            }),
+            program.init
+        );
+    }
+
+    #[test]
+    fn preeval_resume_at_top_level() {
+        // "+.[.]" runs out of the budget inside the loop
+        let bytecodes = vec![ADD(1), PUTC, JZ(5), PUTC, JNZ(3)];
+        let program = preeval(
+            Program {
+                bytecodes,
+                ..Default::default()
+            },
+            100,
+            Limits::default(),
+            Instant::now(),
+        );
+        assert_eq!(vec![PRINT(vec![1]), JZ(4), PUTC, JNZ(2)], program.bytecodes);
+    }
+
+    #[test]
+    fn preeval_within_limits() {
+        // "+.+.+." stopped by the steps the run time is limited to
+        let bytecodes = vec![ADD(1), PUTC, ADD(1), PUTC, ADD(1), PUTC];
+        let limits = Limits {
+            max_steps: Some(3),
+            ..Default::default()
+        };
+        let program = preeval(
+            Program {
+                bytecodes,
+                ..Default::default()
+            },
+            PREEVAL_STEPS,
+            limits,
+            Instant::now(),
+        );
+        assert_eq!(vec![PRINT(vec![1]), PUTC, ADD(1), PUTC], program.bytecodes);
+        assert_eq!(Some(3), program.init.map(|init| init.steps));
+    }
+
+    #[test]
+    fn preeval_starting_with_getc() {
+        // ",."
+        let bytecodes = vec![GETC, PUTC];
+        let program = preeval(
+            Program {
+                bytecodes,
+                ..Default::default()
+            },
+            PREEVAL_STEPS,
+            Limits::default(),
+            Instant::now(),
+        );
+        assert_eq!(vec![GETC, PUTC], program.bytecodes);
+        assert_eq!(None, program.init);
+    }
+}
@@ -0,0 +1,302 @@
+use super::{Program, TapeInit, MEMSIZE, VM};
+use crate::bytecode::OptLevel;
+use std::{error, fmt};
+
+// the header of the binary form
+const MAGIC: &[u8; 3] = b"BFS";
+const VERSION: u8 = 1;
+
+// zero cells to keep inside a region rather than starting another one, which costs about as much
+const GAP: usize = 4;
+
+// the state of a VM, to resume from with VM::restore
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub pc: usize,
+    pub mem_ptr: usize,
+    pub steps: u64,                     // counted against Limits::max_steps
+    pub output: u64,                    // bytes counted against Limits::max_output
+    pub regions: Vec<(usize, Vec<u8>)>, // the tape outside them is zero, as (start, cells)
+}
+
+impl Snapshot {
+    // the tape of init at pc 0, e.g. to start a program optimized up to PEEPHOLE from a prepared
+    // tape
+    pub fn from_tape(init: &TapeInit) -> Self {
+        let mut mem = vec![0; MEMSIZE];
+        for &(addr, v) in init.cells.iter() {
+            mem[addr] = v;
+        }
+        Self {
+            mem_ptr: init.mem_ptr,
+            steps: init.steps,
+            regions: regions(&mem),
+            ..Default::default()
+        }
+    }
+
+    // the binary form: MAGIC, VERSION, then pc, mem_ptr, steps, output, the number of regions
+    // and each region as start, length and cells, all numbers as LEB128
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        for n in [
+            self.pc as u64,
+            self.mem_ptr as u64,
+            self.steps,
+            self.output,
+            self.regions.len() as u64,
+        ] {
+            put_uleb(&mut bytes, n);
+        }
+        for (start, cells) in self.regions.iter() {
+            put_uleb(&mut bytes, *start as u64);
+            put_uleb(&mut bytes, cells.len() as u64);
+            bytes.extend_from_slice(cells);
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let rest = bytes.strip_prefix(MAGIC).ok_or(SnapshotError::BadMagic)?;
+        let (&version, mut rest) = rest.split_first().ok_or(SnapshotError::Truncated)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let mut next = || get_uleb(&mut rest).ok_or(SnapshotError::Truncated);
+        let (pc, mem_ptr, steps, output, count) = (next()?, next()?, next()?, next()?, next()?);
+
+        let mut regions = vec![];
+        for _ in 0..count {
+            let start = get_uleb(&mut rest).ok_or(SnapshotError::Truncated)?;
+            let len = get_uleb(&mut rest).ok_or(SnapshotError::Truncated)? as usize;
+            if rest.len() < len {
+                return Err(SnapshotError::Truncated);
+            }
+            let (cells, tail) = rest.split_at(len);
+            regions.push((start as usize, cells.to_vec()));
+            rest = tail;
+        }
+        let snapshot = Self {
+            pc: pc as usize,
+            mem_ptr: mem_ptr as usize,
+            steps,
+            output,
+            regions,
+        };
+        snapshot.check()?;
+        Ok(snapshot)
+    }
+
+    fn check(&self) -> Result<(), SnapshotError> {
+        let fits = |(start, cells): &(usize, Vec<u8>)| {
+            start
+                .checked_add(cells.len())
+                .map_or(false, |end| end <= MEMSIZE)
+        };
+        if self.mem_ptr >= MEMSIZE || !self.regions.iter().all(fits) {
+            return Err(SnapshotError::OutOfTape);
+        }
+        Ok(())
+    }
+}
+
+// the runs of non-zero cells of mem, joined over short gaps
+fn regions(mem: &[u8]) -> Vec<(usize, Vec<u8>)> {
+    let mut regions: Vec<(usize, Vec<u8>)> = vec![];
+    let mut addr = 0;
+    while addr < mem.len() {
+        if mem[addr] == 0 {
+            addr += 1;
+            continue;
+        }
+        let end = (addr..mem.len())
+            .find(|&i| mem[i] == 0)
+            .unwrap_or(mem.len());
+        match regions.last_mut() {
+            Some((start, cells)) if *start + cells.len() + GAP >= addr => {
+                cells.extend_from_slice(&mem[*start + cells.len()..end]);
+            }
+            _ => regions.push((addr, mem[addr..end].to_vec())),
+        }
+        addr = end;
+    }
+    regions
+}
+
+fn put_uleb(bytes: &mut Vec<u8>, mut n: u64) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+
+fn get_uleb(bytes: &mut &[u8]) -> Option<u64> {
+    let mut n = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&b, rest) = bytes.split_first()?;
+        *bytes = rest;
+        n |= ((b & 0x7f) as u64) << shift;
+        if b < 0x80 {
+            return Some(n);
+        }
+    }
+    None
+}
+
+impl VM {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            mem_ptr: self.mem_ptr,
+            steps: self.steps,
+            output: self.output as u64,
+            regions: regions(&self.mem),
+        }
+    }
+
+    // continue program from snapshot. the limits are kept. FULL assumes a zero tape at the start,
+    // so the program must be optimized up to PEEPHOLE, e.g. with Program::optimize
+    pub fn restore(&mut self, program: &Program, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if program.level > OptLevel::PEEPHOLE {
+            return Err(SnapshotError::Optimized);
+        }
+        snapshot.check()?;
+        self.mem = [0; MEMSIZE];
+        for (start, cells) in snapshot.regions.iter() {
+            self.mem[*start..*start + cells.len()].copy_from_slice(cells);
+        }
+        self.pc = snapshot.pc;
+        self.mem_ptr = snapshot.mem_ptr;
+        self.steps = snapshot.steps;
+        self.output = snapshot.output as usize;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    OutOfTape, // the pointer or a region is outside the tape
+    Optimized, // the program is optimized beyond PEEPHOLE
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SnapshotError::*;
+        match self {
+            BadMagic => write!(f, "not a snapshot"),
+            UnsupportedVersion(v) => write!(f, "unsupported snapshot version {v}"),
+            Truncated => write!(f, "truncated snapshot"),
+            OutOfTape => write!(f, "snapshot outside the tape of {MEMSIZE} cells"),
+            Optimized => write!(f, "cannot restore a program optimized beyond peephole"),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use crate::engine::Interpreter;
+    use crate::vm::Status;
+    use std::io;
+
+    #[test]
+    fn snapshot_regions() {
+        let mut mem = vec![0; 20];
+        mem[1] = 1;
+        mem[2] = 2;
+        mem[6] = 3; // joined over the gap
+        mem[15] = 4;
+        assert_eq!(
+            vec![(1, vec![1, 2, 0, 0, 0, 3]), (15, vec![4])],
+            regions(&mem)
+        );
+    }
+
+    #[test]
+    fn snapshot_bytes() {
+        let snapshot = Snapshot {
+            pc: 300,
+            mem_ptr: MEMSIZE / 2,
+            steps: 1 << 40,
+            output: 0,
+            regions: vec![(0, vec![1, 2]), (MEMSIZE - 1, vec![255])],
+        };
+        let bytes = snapshot.to_bytes();
+        assert_eq!(Ok(snapshot), Snapshot::from_bytes(&bytes));
+        assert_eq!(
+            Err(SnapshotError::Truncated),
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1])
+        );
+        assert_eq!(Err(SnapshotError::BadMagic), Snapshot::from_bytes(b"BF"));
+
+        let outside = Snapshot {
+            regions: vec![(MEMSIZE - 1, vec![1, 1])],
+            ..Default::default()
+        };
+        assert_eq!(
+            Err(SnapshotError::OutOfTape),
+            Snapshot::from_bytes(&outside.to_bytes())
+        );
+    }
+
+    #[test]
+    fn snapshot_resume() {
+        // "+++[.-]"
+        let program = Program {
+            bytecodes: vec![ADD(3), JZ(5), PUTC, ADD(-1), JNZ(2)],
+            ..Default::default()
+        };
+        let mut vm = VM::for_program(&program);
+        let mut output = vec![];
+        vm.run_for(&program, &mut "".as_bytes(), &mut output, 4)
+            .unwrap();
+        let bytes = vm.snapshot().to_bytes();
+
+        let mut vm = VM::new();
+        vm.restore(&program, &Snapshot::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(
+            Ok(Status::Finished),
+            vm.run_for(&program, &mut "".as_bytes(), &mut output, 100)
+        );
+        assert_eq!(vec![3, 2, 1], output);
+    }
+
+    #[test]
+    fn snapshot_restore_levels() {
+        // "[.[-]]" from a cell of 'A', which FULL drops as the tape would be zero
+        let snapshot = Snapshot::from_tape(&TapeInit {
+            mem_ptr: 0,
+            cells: vec![(0, b'A')],
+            steps: 0,
+        });
+        let program = crate::parse("[.[-]]").unwrap();
+        for level in OptLevel::ALL {
+            let program = program.clone().optimize(level);
+            let mut vm = VM::new();
+            let mut output = vec![];
+            match level {
+                OptLevel::FULL => {
+                    assert_eq!(
+                        Err(SnapshotError::Optimized),
+                        vm.restore(&program, &snapshot)
+                    );
+                }
+                _ => {
+                    vm.restore(&program, &snapshot).unwrap();
+                    vm.run(&program, &mut io::empty(), &mut output, &Interpreter)
+                        .unwrap();
+                    assert_eq!(vec![b'A'], output, "{level:?}");
+                }
+            }
+        }
+    }
+}
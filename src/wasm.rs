@@ -0,0 +1,324 @@
+use crate::bytecode::{self, Block, Inst, Node};
+use crate::emit;
+use crate::vm::{Program, EOF, MEMSIZE};
+use std::fmt::Write;
+
+const PAGE: usize = 0x10000;
+
+// the helpers of run, left out of a module not calling them
+const ABORT: &str =
+    "  ;; the pointer q out of the tape after the instruction at pc, returned from run
+  (func $abort (param $q i32) (param $pc i32) (result i32)
+    (global.set $pointer (local.get $q))
+    (i32.add (local.get $pc) (i32.const 1)))
+";
+
+const PUTS: &str = "  (func $puts (param $at i32) (param $len i32)
+    (loop $l
+      (if (local.get $len)
+        (then
+          (call $putc (i32.load8_u (local.get $at)))
+          (local.set $at (i32.add (local.get $at) (i32.const 1)))
+          (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+          (br $l)))))
+";
+
+// a module running program as the VM does, with the tape at 0 in the exported memory and the
+// strings of PRINT after it. it imports env.getc, returning a byte or -1 at the end of the input
+// (read as vm::EOF), and env.putc. the exported run returns 0, or pc + 1 of the instruction
+// moving the pointer out of the tape, as the JIT does, leaving the pointer at the end or out of
+// the tape in the exported global pointer. DEBUG does nothing
+pub fn emit(program: &Program) -> String {
+    let checked = emit::uses(program, emit::checks);
+    let (mem_ptr, cells) = emit::tape(program);
+
+    // the strings of PRINT at their addresses
+    let mut strings = vec![];
+    let mut end = MEMSIZE;
+    for inst in program.bytecodes.iter() {
+        if let Inst::PRINT(s) = inst {
+            strings.push((end, s));
+            end += s.len();
+        }
+    }
+
+    let mut w = ";; generated by bf-jit\n(module\n".to_string();
+    w.push_str("  (import \"env\" \"getc\" (func $getc (result i32)))\n");
+    w.push_str("  (import \"env\" \"putc\" (func $putc (param i32)))\n");
+    let _ = writeln!(
+        w,
+        "  (memory (export \"memory\") {})",
+        (end + PAGE - 1) / PAGE
+    );
+    let _ = writeln!(
+        w,
+        "  (global $pointer (export \"pointer\") (mut i32) (i32.const {mem_ptr}))"
+    );
+    for &(addr, v) in cells.iter() {
+        let _ = writeln!(w, "  (data (i32.const {addr}) \"{}\")", escape(&[v]));
+    }
+    for (addr, s) in strings.iter() {
+        let _ = writeln!(w, "  (data (i32.const {addr}) \"{}\")", escape(s));
+    }
+    for (used, f) in [(checked, ABORT), (!strings.is_empty(), PUTS)] {
+        if used {
+            w.push_str(f);
+        }
+    }
+    w.push_str("  (func (export \"run\") (result i32)\n    (local $p i32) (local $q i32)\n");
+    let _ = writeln!(w, "    (local.set $p (i32.const {mem_ptr}))");
+    let addrs: Vec<_> = strings.iter().map(|&(addr, _)| addr).collect();
+    block(
+        &mut w,
+        &bytecode::raise(&program.bytecodes, &program.spans),
+        0,
+        2,
+        &mut addrs.into_iter(),
+    );
+    w.push_str("    (global.set $pointer (local.get $p))\n    (i32.const 0)))\n");
+    w
+}
+
+// the instructions of block lowered from pc on, taking the addresses of the strings of its PRINTs
+// from `strings`, and returning the pc after it
+fn block(
+    w: &mut String,
+    block: &Block,
+    mut pc: usize,
+    depth: usize,
+    strings: &mut dyn Iterator<Item = usize>,
+) -> usize {
+    let indent = "  ".repeat(depth);
+    for node in block {
+        pc = match node {
+            Node::Op(inst, _) => {
+                let _ = writeln!(w, "{indent}{}", instruction(inst, pc, &indent, strings));
+                pc + 1
+            }
+            Node::Loop { body, .. } => {
+                let _ = writeln!(
+                    w,
+                    "{indent}(if {}\n{indent}  (then\n{indent}    (loop $l{pc}",
+                    load("p")
+                );
+                let exit = self::block(w, body, pc + 1, depth + 3, strings) + 1;
+                let _ = writeln!(w, "{indent}      (br_if $l{pc} {}))))", load("p"));
+                exit
+            }
+            Node::If { body, .. } => {
+                let _ = writeln!(w, "{indent}(if {}\n{indent}  (then", load("p"));
+                let exit = self::block(w, body, pc + 1, depth + 2, strings);
+                close(w);
+                exit
+            }
+        };
+    }
+    pc
+}
+
+// the text of the instruction at pc, other than a jump
+fn instruction(
+    inst: &Inst,
+    pc: usize,
+    indent: &str,
+    strings: &mut dyn Iterator<Item = usize>,
+) -> String {
+    match inst {
+        Inst::MOVPTR(v) => move_to("p", "p", *v, pc, indent),
+        Inst::ADD(v) => add("p", &v.to_string()),
+        Inst::SETZERO => store("p", "(i32.const 0)"),
+        Inst::SET(v) => store("p", &format!("(i32.const {v})")),
+        Inst::MULINTO(coef, offset) => {
+            let product = match coef {
+                1 | -1 => load("p"),
+                _ => format!("(i32.mul {} (i32.const {}))", load("p"), coef.abs()),
+            };
+            format!(
+                "{}\n{indent}{}\n{indent}{}",
+                move_to("q", "p", *offset, pc, indent),
+                if *coef < 0 {
+                    store("q", &format!("(i32.sub {} {product})", load("q")))
+                } else {
+                    store("q", &format!("(i32.add {} {product})", load("q")))
+                },
+                store("p", "(i32.const 0)")
+            )
+        }
+        Inst::FINDZERO(v) => format!(
+            "(block $b{pc}\n{indent}  (loop $l{pc}\n{indent}    (br_if $b{pc} (i32.eqz {}))\n{indent}    {}\n{indent}    (br $l{pc})))",
+            load("p"),
+            move_to("p", "p", *v, pc, &format!("{indent}    "))
+        ),
+        Inst::SCAN(adds, step) => {
+            let inner = format!("{indent}    ");
+            let mut body = format!(
+                "(block $b{pc}\n{indent}  (loop $l{pc}\n{inner}(br_if $b{pc} (i32.eqz {}))\n",
+                load("p")
+            );
+            for (offset, v) in adds.iter() {
+                let _ = writeln!(
+                    body,
+                    "{inner}{}\n{inner}{}",
+                    move_to("q", "p", *offset, pc, &inner),
+                    add("q", &v.to_string())
+                );
+            }
+            let _ = write!(
+                body,
+                "{inner}{}\n{inner}(br $l{pc})))",
+                move_to("p", "p", *step, pc, &inner)
+            );
+            body
+        }
+        Inst::PUTC => format!("(call $putc {})", load("p")),
+        Inst::PRINT(s) => {
+            let addr = strings.next().expect("a string for each PRINT");
+            format!("(call $puts (i32.const {addr}) (i32.const {}))", s.len())
+        }
+        Inst::GETC => format!(
+            "(local.set $q (call $getc))\n{indent}{}",
+            store(
+                "p",
+                &format!(
+                    "(select (i32.const {EOF}) (local.get $q) (i32.lt_s (local.get $q) (i32.const 0)))"
+                )
+            )
+        ),
+        Inst::DEBUG => "(; # does nothing ;)".to_string(),
+        Inst::JZ(_) | Inst::JNZ(_) => unreachable!("raised into loops and ifs"),
+    }
+}
+
+// the parens of an if without a loop, after its last line
+fn close(w: &mut String) {
+    w.insert_str(w.len() - 1, "))");
+}
+
+// set the local to from + v, returning from run if it is out of the tape as the VM checks it
+fn move_to(local: &str, from: &str, v: isize, pc: usize, indent: &str) -> String {
+    format!(
+        "(local.set ${local} (i32.add (local.get ${from}) (i32.const {v})))\n{indent}(if (i32.ge_u (local.get ${local}) (i32.const {MEMSIZE}))\n{indent}  (then (return (call $abort (local.get ${local}) (i32.const {pc})))))"
+    )
+}
+
+fn load(local: &str) -> String {
+    format!("(i32.load8_u (local.get ${local}))")
+}
+
+fn store(local: &str, value: &str) -> String {
+    format!("(i32.store8 (local.get ${local}) {value})")
+}
+
+fn add(local: &str, v: &str) -> String {
+    store(local, &format!("(i32.add {} (i32.const {v}))", load(local)))
+}
+
+// the bytes of a string of the text format
+fn escape(s: &[u8]) -> String {
+    emit::escape(s, b"\"\\", |b| format!("\\{b:02x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Inst::*;
+    use crate::vm::TapeInit;
+
+    #[test]
+    fn emit_wat() {
+        // a loop, an if left open at the end and a PRINT to escape
+        let program = Program {
+            bytecodes: vec![
+                GETC,
+                JZ(4),
+                MULINTO(2, 1),
+                JNZ(2),
+                MOVPTR(1),
+                JZ(8),
+                PRINT(b"\"a\"\n\x01".to_vec()),
+                DEBUG,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            r#";; generated by bf-jit
+(module
+  (import "env" "getc" (func $getc (result i32)))
+  (import "env" "putc" (func $putc (param i32)))
+  (memory (export "memory") 2)
+  (global $pointer (export "pointer") (mut i32) (i32.const 50000))
+  (data (i32.const 100000) "\"a\"\n\01")
+  ;; the pointer q out of the tape after the instruction at pc, returned from run
+  (func $abort (param $q i32) (param $pc i32) (result i32)
+    (global.set $pointer (local.get $q))
+    (i32.add (local.get $pc) (i32.const 1)))
+  (func $puts (param $at i32) (param $len i32)
+    (loop $l
+      (if (local.get $len)
+        (then
+          (call $putc (i32.load8_u (local.get $at)))
+          (local.set $at (i32.add (local.get $at) (i32.const 1)))
+          (local.set $len (i32.sub (local.get $len) (i32.const 1)))
+          (br $l)))))
+  (func (export "run") (result i32)
+    (local $p i32) (local $q i32)
+    (local.set $p (i32.const 50000))
+    (local.set $q (call $getc))
+    (i32.store8 (local.get $p) (select (i32.const 0) (local.get $q) (i32.lt_s (local.get $q) (i32.const 0))))
+    (if (i32.load8_u (local.get $p))
+      (then
+        (loop $l1
+          (local.set $q (i32.add (local.get $p) (i32.const 1)))
+          (if (i32.ge_u (local.get $q) (i32.const 100000))
+            (then (return (call $abort (local.get $q) (i32.const 2)))))
+          (i32.store8 (local.get $q) (i32.add (i32.load8_u (local.get $q)) (i32.mul (i32.load8_u (local.get $p)) (i32.const 2))))
+          (i32.store8 (local.get $p) (i32.const 0))
+          (br_if $l1 (i32.load8_u (local.get $p))))))
+    (local.set $p (i32.add (local.get $p) (i32.const 1)))
+    (if (i32.ge_u (local.get $p) (i32.const 100000))
+      (then (return (call $abort (local.get $p) (i32.const 4)))))
+    (if (i32.load8_u (local.get $p))
+      (then
+        (call $puts (i32.const 100000) (i32.const 5))
+        (; # does nothing ;)))
+    (global.set $pointer (local.get $p))
+    (i32.const 0)))
+"#,
+            emit(&program)
+        );
+    }
+
+    #[test]
+    fn emit_wat_init() {
+        // the tape set by preeval, and the pointer but no checks without a move
+        let program = Program {
+            bytecodes: vec![ADD(-3), PUTC],
+            spans: vec![],
+            init: Some(TapeInit {
+                mem_ptr: 10,
+                cells: vec![(10, 7), (11, b'"')],
+                steps: 0,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            r#";; generated by bf-jit
+(module
+  (import "env" "getc" (func $getc (result i32)))
+  (import "env" "putc" (func $putc (param i32)))
+  (memory (export "memory") 2)
+  (global $pointer (export "pointer") (mut i32) (i32.const 10))
+  (data (i32.const 10) "\07")
+  (data (i32.const 11) "\"")
+  (func (export "run") (result i32)
+    (local $p i32) (local $q i32)
+    (local.set $p (i32.const 10))
+    (i32.store8 (local.get $p) (i32.add (i32.load8_u (local.get $p)) (i32.const -3)))
+    (call $putc (i32.load8_u (local.get $p)))
+    (global.set $pointer (local.get $p))
+    (i32.const 0)))
+"#,
+            emit(&program)
+        );
+    }
+}
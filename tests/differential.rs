@@ -0,0 +1,269 @@
+// runs random well-formed programs with the interpreter and the JIT and compares the output, the
+// final tape, the steps and the error, some of them running off an edge of the tape. a failing
+// program is shrunk before it is reported.
+// BF_DIFF_SEED and BF_DIFF_CASES override the seed and the number of programs
+
+use bf_jit::{Engine, EngineConfig, Interpreter, Jit, Limits, RunResult};
+use std::env;
+
+const SEED: u64 = 0x5eed_b1a5_ed0b_f00d;
+const CASES: usize = 300;
+
+// programs taking more steps on the interpreter are not compared, e.g. the shrunk ones
+const MAX_STEPS: u64 = 10_000_000;
+
+// xorshift64*
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// builds a program keeping track of the pointer relative to the start
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    codes: String,
+    offset: isize,
+    counters: Vec<isize>, // cells of the enclosing loops, only decremented at their ends
+}
+
+impl Generator<'_> {
+    fn block(&mut self, depth: usize) {
+        for _ in 0..1 + self.rng.below(8) {
+            let free = !self.counters.contains(&self.offset);
+            match self.rng.below(9) {
+                0 | 1 if free => {
+                    let c = if self.rng.below(2) == 0 { '+' } else { '-' };
+                    let n = 1 + self.rng.below(12);
+                    self.repeat(c, n);
+                }
+                2 | 3 => {
+                    let to = self.offset + self.rng.below(7) as isize - 3;
+                    self.move_to(to);
+                }
+                4 => self.codes.push('.'),
+                5 if free => self.codes.push(','),
+                6 if free => self.codes.push_str("[-]"),
+                7 if free => {
+                    // a transfer loop like "[->++<]"
+                    let from = self.offset;
+                    let to = from + self.rng.below(7) as isize - 3;
+                    if to == from || self.counters.contains(&to) {
+                        continue;
+                    }
+                    self.codes.push_str("[-");
+                    self.move_to(to);
+                    let c = if self.rng.below(2) == 0 { '+' } else { '-' };
+                    let n = 1 + self.rng.below(3);
+                    self.repeat(c, n);
+                    self.move_to(from);
+                    self.codes.push(']');
+                }
+                8 if free && depth < 2 => {
+                    // at most 255 iterations, as the body leaves the counter alone
+                    let counter = self.offset;
+                    self.codes.push('[');
+                    self.counters.push(counter);
+                    self.block(depth + 1);
+                    self.counters.pop();
+                    self.move_to(counter);
+                    self.codes.push_str("-]");
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn repeat(&mut self, c: char, n: usize) {
+        self.codes.extend(std::iter::repeat(c).take(n));
+    }
+
+    fn move_to(&mut self, to: isize) {
+        let c = if to > self.offset { '>' } else { '<' };
+        self.repeat(c, (to - self.offset).unsigned_abs());
+        self.offset = to;
+    }
+}
+
+// the offsets of the first and the last cells from the start
+const LEFT_EDGE: isize = -50000;
+const RIGHT_EDGE: isize = 49999;
+
+// with edge, the program starts with a long run to a few cells from an edge and ends with a scan
+// or a loop towards it, which may leave the tape
+fn generate(rng: &mut Rng, edge: bool) -> (String, Vec<u8>) {
+    let mut generator = Generator {
+        rng,
+        codes: String::new(),
+        offset: 0,
+        counters: vec![],
+    };
+    let right = generator.rng.below(2) == 0;
+    if edge {
+        let distance = generator.rng.below(8) as isize;
+        generator.move_to(if right {
+            RIGHT_EDGE - distance
+        } else {
+            LEFT_EDGE + distance
+        });
+    }
+    generator.block(0);
+    if edge {
+        let tail = ["[>]", "[>>]", "+[>+]", "[>-]>[+>]"][generator.rng.below(4)];
+        let tail = if right {
+            tail.to_string()
+        } else {
+            tail.replace('>', "<")
+        };
+        generator.codes.push_str(&tail);
+    }
+    let codes = generator.codes;
+    let input = (0..rng.below(8)).map(|_| rng.next() as u8).collect();
+    (codes, input)
+}
+
+// the output, and the status, the steps and the state at the end or the error
+type Outcome = (Vec<u8>, RunResult);
+
+fn run(engine: &dyn Engine, codes: &str, input: &[u8], limits: Limits) -> Outcome {
+    let config = EngineConfig {
+        limits,
+        ..Default::default()
+    };
+    let program = config.compile(codes).unwrap();
+    let mut output = vec![];
+    let result = engine.execute(&program, &config, &mut &input[..], &mut output);
+    (output, result)
+}
+
+// how the engines disagree on codes, None if they agree or codes runs too long
+fn differ(codes: &str, input: &[u8]) -> Option<String> {
+    let limits = Limits {
+        max_steps: Some(MAX_STEPS),
+        ..Default::default()
+    };
+    let expected = run(&Interpreter, codes, input, limits);
+    if matches!(&expected.1.status, Err(e) if e.to_string().contains("step limit")) {
+        return None;
+    }
+    let found = run(&Jit, codes, input, Limits::default());
+    if expected == found {
+        return None;
+    }
+    Some(format!(
+        "interpreter: {:?}\n        jit: {:?}",
+        expected, found
+    ))
+}
+
+// the position of the "]" closing the "[" at open
+fn close_of(codes: &[u8], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, &c) in codes.iter().enumerate().skip(open) {
+        match c {
+            b'[' => depth += 1,
+            b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => (),
+        }
+    }
+    unreachable!("unbalanced program")
+}
+
+// smaller programs than codes keeping the brackets balanced
+fn candidates(codes: &str) -> Vec<String> {
+    let bytes = codes.as_bytes();
+    let mut candidates = vec![];
+    // half of each run first, as the runs to an edge are long
+    let mut start = 0;
+    for (i, &c) in bytes.iter().enumerate() {
+        if bytes.get(i + 1) != Some(&c) {
+            if c != b'[' && c != b']' && i - start >= 1 {
+                let mut candidate = bytes.to_vec();
+                candidate.drain(start..start + (i + 1 - start) / 2);
+                candidates.push(candidate);
+            }
+            start = i + 1;
+        }
+    }
+    for (i, &c) in bytes.iter().enumerate() {
+        let mut candidate = bytes.to_vec();
+        match c {
+            b'[' => {
+                let close = close_of(bytes, i);
+                // the whole loop, then the brackets alone
+                candidate.drain(i..=close);
+                candidates.push(candidate);
+                let mut candidate = bytes.to_vec();
+                candidate.remove(close);
+                candidate.remove(i);
+                candidates.push(candidate);
+            }
+            b']' => continue,
+            // the same as removing the previous one
+            _ if i > 0 && bytes[i - 1] == c => continue,
+            _ => {
+                candidate.remove(i);
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+        .into_iter()
+        .map(|candidate| String::from_utf8(candidate).unwrap())
+        .collect()
+}
+
+// the smallest program found still failing
+fn shrink(mut codes: String, fails: &dyn Fn(&str) -> bool) -> String {
+    while let Some(smaller) = candidates(&codes)
+        .into_iter()
+        .find(|candidate| fails(candidate))
+    {
+        codes = smaller;
+    }
+    codes
+}
+
+#[test]
+fn interpreter_and_jit_agree() {
+    let seed = env::var("BF_DIFF_SEED").map_or(SEED, |s| s.parse().unwrap());
+    let cases = env::var("BF_DIFF_CASES").map_or(CASES, |s| s.parse().unwrap());
+    let mut rng = Rng(seed);
+    for case in 0..cases {
+        let (codes, input) = generate(&mut rng, case % 4 == 3);
+        if differ(&codes, &input).is_some() {
+            let codes = shrink(codes, &|codes| differ(codes, &input).is_some());
+            panic!(
+                "case {case} of seed {seed}: the engines disagree on {codes:?} with input {input:?}\n{}",
+                differ(&codes, &input).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn shrink_to_minimal() {
+    // a stand-in difference: any program printing twice
+    let prints_twice = |codes: &str| codes.matches('.').count() >= 2;
+    assert_eq!("..", shrink("+[->>.<<]>.<,-".to_string(), &prints_twice));
+    assert_eq!(
+        "[.].",
+        shrink("+[[-.]>]>.<,-".to_string(), &|codes| {
+            prints_twice(codes) && codes.starts_with('[')
+        })
+    );
+}
@@ -0,0 +1,111 @@
+// runs each examples/*.bf under every engine and optimization level, and compares the output with
+// the sidecar .out and the error with .err (none if missing), feeding .in as the input
+
+use bf_jit::{Limits, OptLevel, RuntimeError, VM};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const LEVELS: [OptLevel; 3] = [OptLevel::NONE, OptLevel::PEEPHOLE, OptLevel::FULL];
+
+// the interpreter in a debug build would take too long on examples like mandelbrot.bf, which are
+// left to the JIT beyond this
+const INTERPRETER_STEPS: u64 = 20_000_000;
+
+// the examples which may not run under the interpreter within INTERPRETER_STEPS at any level,
+// checked by examples_golden_full instead
+const SLOW: [&str; 1] = ["mandelbrot.bf"];
+
+fn sidecar(path: &Path, extension: &str) -> Option<Vec<u8>> {
+    fs::read(path.with_extension(extension)).ok()
+}
+
+fn examples() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "bf"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// the output and the error, or None if the interpreter hit max_steps
+fn run(
+    codes: &str,
+    input: &[u8],
+    engine: &str,
+    level: OptLevel,
+    max_steps: Option<u64>,
+) -> Option<(Vec<u8>, String)> {
+    let program = match bf_jit::parse(codes) {
+        Ok(program) => program.optimize(level),
+        Err(e) => return Some((vec![], e.to_string())),
+    };
+    let mut vm = VM::for_program(&program);
+    if engine == "interp" {
+        vm.limit(Limits {
+            max_steps,
+            ..Default::default()
+        });
+    }
+    let mut output = vec![];
+    let engine = bf_jit::engine(engine).unwrap();
+    match vm.run(&program, &mut &input[..], &mut output, engine.as_ref()) {
+        Ok(()) => Some((output, String::new())),
+        Err(RuntimeError::StepLimit { .. }) => None,
+        Err(e) => Some((output, e.to_string())),
+    }
+}
+
+// check every example, and return the (example, engine) pairs not run at any level
+fn check(max_steps: Option<u64>) -> Vec<(String, &'static str)> {
+    let paths = examples();
+    assert!(!paths.is_empty());
+    let mut skipped = vec![];
+    for path in paths {
+        let codes = fs::read_to_string(&path).unwrap();
+        let input = sidecar(&path, "in").unwrap_or_default();
+        let output = sidecar(&path, "out").unwrap_or_default();
+        let error = sidecar(&path, "err").map_or(String::new(), |e| {
+            String::from_utf8(e).unwrap().trim_end().to_string()
+        });
+
+        for engine in bf_jit::ENGINES {
+            let mut ran = false;
+            for level in LEVELS {
+                let (found_output, found_error) =
+                    match run(&codes, &input, engine, level, max_steps) {
+                        Some(res) => res,
+                        None => continue,
+                    };
+                ran = true;
+                let case = format!("{} with {engine} at {level:?}", path.display());
+                assert_eq!(error, found_error, "the error of {case}");
+                assert!(output == found_output, "the output of {case} differs");
+            }
+            if !ran {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                skipped.push((name, engine));
+            }
+        }
+    }
+    skipped
+}
+
+#[test]
+fn examples_golden() {
+    for (name, engine) in check(Some(INTERPRETER_STEPS)) {
+        assert!(
+            engine == "interp" && SLOW.contains(&name.as_str()),
+            "{name} did not run with {engine} at any level, add it to SLOW"
+        );
+    }
+}
+
+// cargo test --release --test examples -- --ignored
+#[test]
+#[ignore = "the interpreter takes minutes on SLOW in a debug build"]
+fn examples_golden_full() {
+    assert_eq!(Vec::<(String, &str)>::new(), check(None));
+}